@@ -0,0 +1,37 @@
+#![no_main]
+
+// Coverage-guided fuzzing of an allocator module with libFuzzer. Run it with
+// `cargo fuzz run allocator`. The module defaults to src/2_linked.wat and can
+// be chosen at build or run time with the WASMALLOC_MODULE environment
+// variable, e.g. `WASMALLOC_MODULE=src/1_minimal.wat cargo fuzz run allocator`.
+
+use libfuzzer_sys::fuzz_target;
+use std::env;
+use std::sync::OnceLock;
+use wasmtime::{Engine, Module};
+
+// The fuzz target shares the decoding and checks with the `fuzz` subcommand.
+use wasmalloc::{fuzz, WasmAllocator};
+
+// module compiles the allocator module once for all inputs.
+fn module() -> &'static (Engine, Module) {
+    static MODULE: OnceLock<(Engine, Module)> = OnceLock::new();
+    MODULE.get_or_init(|| {
+        let path = env::var("WASMALLOC_MODULE")
+            .ok()
+            .or(option_env!("WASMALLOC_MODULE").map(String::from))
+            .unwrap_or_else(|| concat!(env!("CARGO_MANIFEST_DIR"), "/../src/2_linked.wat").to_string());
+        let engine = WasmAllocator::engine().expect("failed to create engine");
+        let module = Module::from_file(&engine, &path).unwrap_or_else(|err| panic!("{}: {}", path, err));
+        (engine, module)
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let (engine, module) = module();
+    let allocator = WasmAllocator::instantiate(engine, module).expect("failed to instantiate module");
+    let ops = fuzz::decode(data);
+    if let Some(bug) = fuzz::check(allocator, &ops, &[]) {
+        panic!("bug at operation {} ({}): {}", bug.index, ops[bug.index], bug.message);
+    }
+});
@@ -5,8 +5,8 @@
 ;; It's the fastest in allocation and deallocation speed, but it's extremely
 ;; unoptimal in terms of memory usage - it never deallocates.
 (module
-    ;; An empty memory.
-    (memory 0)
+    ;; An empty memory. It's exported so the host can inspect it.
+    (memory (export "memory") 0)
 
     ;; $next is the address to be returned in the next call to $alloc.
     ;; It's the start of the free memory area. It increases whenever $alloc
@@ -26,8 +26,9 @@
 ;;  └──────────┘ └──────────┘
 ;;
 (module
-    ;; A 1-page memory. One page is 64KiB = 65536 bytes.
-    (memory 1)
+    ;; A 1-page memory. One page is 64KiB = 65536 bytes. It's exported so the
+    ;; host can inspect it.
+    (memory (export "memory") 1)
 
     ;; Initialize the first two 32-bit words in memory to be the header of a
     ;; free block that spans the entire memory. The first 32-bit word is the
@@ -49,17 +50,20 @@
     ;; resulting in lower internal fragmentation but leading to greater external
     ;; fragmentation. Increasing $max_extra, on the other hand, will cause
     ;; blocks to be split less frequently, resulting in greater internal
-    ;; fragmentation but lower external fragmentation. The minimum value that
-    ;; results in a useful post-split block is 2 (32-bit) words. The default is
-    ;; 4 words, which means that only blocks which exceed $size by 5 words or
-    ;; more will be split, resulting in a post-split block of at least 4 words.
-    (global $max_extra (mut i32) (i32.const 4))
-
-    ;; $alloc allocates a block of memory of the specified size.
-    ;; If $size is zero, nothing is allocated and zero is returned.
-    ;; The memory is grown if necessary.
+    ;; fragmentation but lower external fragmentation. Sizes are in bytes, and
+    ;; the minimum value that results in a useful post-split block is 2 (32-bit)
+    ;; words, i.e. 8 bytes. The default is 4 words, which means that only blocks
+    ;; which exceed $size by 5 words or more will be split, resulting in a
+    ;; post-split block of at least 4 words (the fifth holds its header).
+    (global $max_extra (mut i32) (i32.const 16))
+
+    ;; $alloc allocates a block of memory of the specified size in bytes.
+    ;; Sizes are rounded up to a whole number of 32-bit words, so a block may
+    ;; be up to 3 bytes larger than requested. If $size is zero, nothing is
+    ;; allocated and zero is returned. The memory is grown if necessary, and if
+    ;; it can't be grown zero is returned too, as there's no block to return.
     (func $alloc (export "alloc")
-        (param $size i32) ;; size of the requested block (in 32-bit words)
+        (param $size i32) ;; size of the requested block (in bytes)
         (result i32) ;; address of the allocated block
 
         ;; Three locals that we'll use.
@@ -67,7 +71,20 @@
         (local $curr i32) ;; the address of current block in the free list
         (local $temp i32) ;; temporary value
 
-        ;; If the requested $size is zero, just return zero.
+        ;; Round $size up to a whole number of 32-bit words, so that every
+        ;; block and header stays aligned.
+        (local.set $size ;; ($size + 3) & ~3
+            (i32.and
+                (i32.add
+                    (local.get $size)
+                    (i32.const 3)
+                )
+                (i32.const -4)
+            )
+        )
+
+        ;; If the requested $size is zero, just return zero. This includes
+        ;; sizes so large that rounding them up wraps around.
         (if ;; $size == 0
             (i32.eqz (local.get $size))
             (return (i32.const 0)) ;; return 0
@@ -81,11 +98,11 @@
         ;; the requested size we'll grow the memory and create a new block.
         ;; Either way, at the end of the loop $curr will point to a block that
         ;; we can use.
-        (loop $loop
+        (block $found (loop $loop
 
             ;; Break the loop if the size of the current block is sufficient.
-            (br_if $loop ;; sizeOf($curr) >= $size
-                (i32.ge_s
+            (br_if $found ;; sizeOf($curr) >= $size
+                (i32.ge_u
                     (i32.load ;; sizeOf($curr)
                         (i32.sub
                             (local.get $curr)
@@ -118,31 +135,44 @@
                     ;; the free list that satisfies $size. Therefore we need to
                     ;; grow the memory by at least one page.
 
-                    ;; Below we grow the memory by 1 + ($size + 4) / 65536 and
-                    ;; set $curr = 4 + (page size) * (old memory size), which is
-                    ;; the start of the newly grown area. We also repurpose
-                    ;; $temp and set it to the newly grown size (in number of
-                    ;; pages). We also push it to the stack via `local.tee` so
-                    ;; it can be used immediately by `memory.grow`.
+                    ;; Below we grow the memory by $temp = 1 + $size / 65536
+                    ;; pages, which leaves room for the header as $size is a
+                    ;; whole number of words. We push $temp to the stack via
+                    ;; `local.tee` so it can be used immediately by
+                    ;; `memory.grow`, which returns the old memory size (in
+                    ;; pages) that we store in $curr, or -1 if the memory can't
+                    ;; be grown.
+                    (local.set $curr
+                        (memory.grow
+                            (local.tee $temp
+                                (i32.add ;; 1 + $size / 65536
+                                    (i32.const 1) ;; at least one page
+                                    (i32.div_u
+                                        (local.get $size)
+                                        (i32.const 65536) ;; page size
+                                    )
+                                )
+                            )
+                        )
+                    )
+
+                    ;; If the memory can't be grown, there's no block to return.
+                    (if ;; $curr == -1
+                        (i32.eq
+                            (local.get $curr)
+                            (i32.const -1)
+                        )
+                        (return (i32.const 0)) ;; return 0
+                    )
+
+                    ;; Set $curr = 4 + (page size) * (old memory size), which is
+                    ;; the start of the newly grown area.
                     (local.set $curr
-                        (i32.add ;; 4 + (page size) * (old memory size)
+                        (i32.add
                             (i32.const 4) ;; header
                             (i32.mul ;; address of new page(s)
                                 (i32.const 65536) ;; page size
-                                (memory.grow ;; old memory size
-                                    (local.tee $temp
-                                        (i32.add ;; 1 + ($size + 1) / 65536
-                                            (i32.const 1) ;; at least one page
-                                            (i32.div_u
-                                                (i32.add
-                                                    (local.get $size)
-                                                    (i32.const 1) ;; header
-                                                )
-                                                (i32.const 65536)
-                                            )
-                                        )
-                                    )
-                                )
+                                (local.get $curr) ;; old memory size
                             )
                         )
                     )
@@ -211,9 +241,12 @@
                                     (local.get $curr)
                                     (i32.const 4)
                                 )
-                                (i32.mul ;; newly grown size
-                                    (local.get $temp) ;; newly grown page(s)
-                                    (i32.const 65536) ;; page size
+                                (i32.sub ;; newly grown size - 4
+                                    (i32.mul ;; newly grown size
+                                        (local.get $temp) ;; newly grown page(s)
+                                        (i32.const 65536) ;; page size
+                                    )
+                                    (i32.const 4) ;; header
                                 )
                             )
 
@@ -236,16 +269,19 @@
                     ;; End the loop. At this point $curr points to a free block
                     ;; that comprises at least the entire newly grown area of
                     ;; memory and, more importantly, that satisfies $size.
-                    (br $loop)
+                    (br $found)
                 )
             )
-        )
+
+            ;; Check the next block.
+            (br $loop)
+        ))
 
         ;; At this point $curr points to a free block that satisfies $size.
         ;; If the block is too large, we'll split it in two. This uses the
         ;; global configurable parameter $max_extra.
         (if
-            (i32.gt_s ;; sizeOf($curr) > $size + $max_extra
+            (i32.gt_u ;; sizeOf($curr) > $size + $max_extra
                 (i32.load ;; sizeOf($curr)
                     (i32.sub
                         (local.get $curr)
@@ -263,31 +299,38 @@
                 ;; already correctly points to the next free block, and we'll
                 ;; return the second resulting block.
 
-                ;; Update the size of the first block and also store it into
-                ;; $temp via `local.tee`.
+                ;; Update the size of the first block, which leaves room for
+                ;; the header of the second one, and also store it into $temp
+                ;; via `local.tee`.
                 (i32.store
                     (i32.sub ;; address of the first split block's size
                         (local.get $curr)
                         (i32.const 4)
                     )
                     (local.tee $temp
-                        (i32.sub ;; sizeOf($curr) - $size
+                        (i32.sub ;; sizeOf($curr) - $size - 4
                             (i32.load ;; sizeOf($curr)
                                 (i32.sub
                                     (local.get $curr)
                                     (i32.const 4)
                                 )
                             )
-                            (local.get $size)
+                            (i32.add
+                                (local.get $size)
+                                (i32.const 4) ;; header
+                            )
                         )
                     )
                 )
 
                 ;; Update $curr to point to the address of the second block.
-                (local.set $curr ;; $curr = $curr + (sizeOf($curr) - $size)
+                (local.set $curr ;; $curr = $curr + (sizeOf($curr) - $size - 4) + 4
                     (i32.add
                         (local.get $curr)
-                        (local.get $temp) ;; sizeOf($curr) - $size
+                        (i32.add
+                            (local.get $temp) ;; sizeOf($curr) - $size - 4
+                            (i32.const 4) ;; header
+                        )
                     )
                 )
 
@@ -310,9 +353,17 @@
                 ;; the current block. If $curr is the last block, then this will
                 ;; set the previous block's next-block pointer to zero, which
                 ;; will correctly indicate that it's the last block in the list.
-                (i32.store
-                    (local.get $prev)
-                    (i32.load (local.get $curr))
+                ;; If $curr is the first block, there's no previous block and
+                ;; $free points to the block after it instead.
+                (if ;; $prev == 0
+                    (i32.eqz (local.get $prev))
+                    (then (global.set $free (i32.load (local.get $curr))))
+                    (else
+                        (i32.store
+                            (local.get $prev)
+                            (i32.load (local.get $curr))
+                        )
+                    )
                 )
             )
         )
@@ -346,11 +397,14 @@
         ;; zero. If the block to deallocate is the last block, we won't find a
         ;; block whose adddress is greater and the loop will end with $prev
         ;; pointing to the last free block in the free list.
-        (loop $loop
+        (block $found (loop $loop
+
+            ;; Break the loop at the end of the free list.
+            (br_if $found (i32.eqz (local.get $curr)))
 
             ;; Break the loop if $curr > $address.
-            (br_if $loop ;; $curr > $address
-                (i32.gt_s
+            (br_if $found ;; $curr > $address
+                (i32.gt_u
                     (local.get $curr)
                     (local.get $address)
                 )
@@ -361,7 +415,10 @@
 
             ;; Set $curr to the next block in the free list.
             (local.set $curr (i32.load (local.get $curr)))
-        )
+
+            ;; Check the next block.
+            (br $loop)
+        ))
 
         ;; At this point $prev is either zero or it's the address of the block
         ;; before the one we want to deallocate.
@@ -404,7 +461,9 @@
         ;; TODO: check if neighboring blocks are free and coalesce if so.
     )
 
-    ;; $realloc reallocates a previously allocated block with a new size.
+    ;; $realloc reallocates a previously allocated block with a new size in
+    ;; bytes. If no block of the new size can be allocated, zero is returned
+    ;; and the original block is left as is.
     (func $realloc (export "realloc")
         (param $address i32) ;; address of the previously allocated block
         (param $size i32) ;; new size of the block in bytes
@@ -418,7 +477,7 @@
         ;; perspective we're "growing" the block. This would happen if there
         ;; was some internal fragmentation in the original block.
         (if ;; $size <= sizeOf($address)
-            (i32.le_s
+            (i32.le_u
                 (local.get $size)
                 (i32.load ;; sizeOf($address)
                     (i32.sub
@@ -434,15 +493,21 @@
         ;; The new size doesn't fit in the current block so we'll allocate
         ;; another block, copy the contents, and deallocate the old one.
 
-        ;; Copy the contents of the old block to a new block.
+        ;; Allocate the new block, keeping the original one if that fails.
+        (local.set $new (call $alloc (local.get $size)))
+        (if ;; $new == 0
+            (i32.eqz (local.get $new))
+            (then (return (i32.const 0))) ;; return 0
+        )
+
+        ;; Copy the contents of the old block to the new block.
         (memory.copy
-            ;; The destination is the address of a new block that we allocate by
-            ;; calling $alloc. We store the new block's address in $new.
-            (local.tee $new (call $alloc (local.get $size)))
+            ;; The destination is the address of the new block.
+            (local.get $new)
              ;; The source is the address of the original block.
             (local.get $address)
-            ;; The number of bytes to copy is the size of the original block
-            ;; (which is an amount of 32-bit words) times four bytes.
+            ;; The number of bytes to copy is the size of the original block,
+            ;; which is smaller than the new size.
             (i32.load ;; sizeOf($address)
                 (i32.sub
                     (local.get $address)
@@ -458,6 +523,116 @@
         (return (local.get $new))
     )
 
+    ;; Heap walk functions, which let the host enumerate every block in address
+    ;; order to visualize the heap and check it's consistent. Blocks are laid out
+    ;; back to back from the start of the memory, each preceded by its header.
+
+    ;; $heap_first returns the address of the first block, which is always
+    ;; right after the header at the start of the memory.
+    (func $heap_first (export "heap_first")
+        (result i32) ;; address of the first block
+        (i32.const 4)
+    )
+
+    ;; $heap_next returns the address of the block after $block, or zero if
+    ;; $block is the last block in memory.
+    (func $heap_next (export "heap_next")
+        (param $block i32) ;; address of a block
+        (result i32) ;; address of the next block, or zero
+
+        (local $next i32) ;; the address of the next block
+
+        ;; The next block starts after this block's data and the next block's
+        ;; header.
+        (local.set $next ;; $block + sizeOf($block) + 4
+            (i32.add
+                (local.get $block)
+                (i32.add
+                    (i32.load ;; sizeOf($block)
+                        (i32.sub
+                            (local.get $block)
+                            (i32.const 4)
+                        )
+                    )
+                    (i32.const 4) ;; header
+                )
+            )
+        )
+        ;; If the next block would start past the end of the memory, $block is
+        ;; the last one.
+        (if ;; $next >= (page size) * memory.size
+            (i32.ge_u
+                (local.get $next)
+                (i32.mul
+                    (i32.const 65536) ;; page size
+                    (memory.size)
+                )
+            )
+            (return (i32.const 0)) ;; return 0
+        )
+        (return (local.get $next))
+    )
+
+    ;; $block_size returns the size of $block in bytes, from its header.
+    (func $block_size (export "block_size")
+        (param $block i32) ;; address of a block
+        (result i32) ;; size of the block in bytes
+        (i32.load ;; sizeOf($block)
+            (i32.sub
+                (local.get $block)
+                (i32.const 4)
+            )
+        )
+    )
+
+    ;; $block_is_free returns 1 if $block is in the free list and 0 otherwise.
+    (func $block_is_free (export "block_is_free")
+        (param $block i32) ;; address of a block
+        (result i32) ;; 1 if the block is free, 0 otherwise
+
+        (local $curr i32) ;; the address of current block in the free list
+
+        ;; Initialize $curr = $free.
+        (local.set $curr (global.get $free))
+
+        ;; Traverse the free list until we find $block or reach its end.
+        (block $done
+            (loop $loop
+                ;; Stop at the end of the free list.
+                (br_if $done (i32.eqz (local.get $curr)))
+                ;; If $curr is $block, then $block is free.
+                (if ;; $curr == $block
+                    (i32.eq
+                        (local.get $curr)
+                        (local.get $block)
+                    )
+                    (return (i32.const 1)) ;; return 1
+                )
+                ;; Set $curr to the next block in the free list.
+                (local.set $curr (i32.load (local.get $curr)))
+                (br $loop)
+            )
+        )
+        ;; $block isn't in the free list.
+        (return (i32.const 0))
+    )
+
+    ;; $free_first returns the address of the first block in the free list, or
+    ;; zero if the free list is empty.
+    (func $free_first (export "free_first")
+        (result i32) ;; address of the first free block, or zero
+        (global.get $free)
+    )
+
+    ;; $free_next returns the address of the block after $block in the free
+    ;; list, which is stored in its next-block pointer, or zero if $block is the
+    ;; last one.
+    (func $free_next (export "free_next")
+        (param $block i32) ;; address of a free block
+        (result i32) ;; address of the next free block, or zero
+        (i32.load (local.get $block))
+    )
+
     ;; Auxiliary functions for testing.
     (func $store (export "store") (param $address i32) (param $value i32)
         (i32.store (local.get $address) (local.get $value))
@@ -0,0 +1,582 @@
+use crate::interface;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use wasmtime::*;
+
+// A WasmAllocator wraps an instance of an allocator module, so that tools can
+// call it like a Rust allocator. The module must export these functions:
+//
+//     alloc(size: i32) -> i32
+//     dealloc(address: i32)
+//     realloc(address: i32, size: i32) -> i32
+//
+// It can also export these helpers, which the harness otherwise replaces with
+// direct accesses to its memory, exported as "memory":
+//
+//     store(address: i32, value: i32)
+//     load(address: i32) -> i32
+//     size() -> i32
+//     grow(pages: i32) -> i32
+//
+// The memory is also needed for the byte accessors to work.
+
+// FUEL_PER_CALL is the maximum amount of fuel (roughly, the number of
+// WebAssembly instructions) a single call into the module may consume. It
+// turns allocator bugs that loop forever into traps instead of hangs.
+const FUEL_PER_CALL: u64 = 100_000_000;
+
+// The optional heap walk convention lets the host enumerate every block of the
+// allocator in address order:
+//
+//     heap_first() -> i32          address of the first block, or 0 if none
+//     heap_next(block) -> i32      address of the block after it, or 0
+//     block_size(block) -> i32     usable size of the block in bytes
+//     block_is_free(block) -> i32  1 if the block is free, 0 if it's in use
+//
+// A block's address is the one alloc returns for it. Memory between blocks
+// holds their headers and the allocator's other metadata. Allocators with a
+// free list can also export it, and say whether they coalesce free blocks, so
+// that the heap can be checked for consistency:
+//
+//     free_first() -> i32          first block of the free list, or 0 if empty
+//     free_next(block) -> i32      block after it in the free list, or 0
+//     heap_coalesces() -> i32      1 if no two free blocks should be adjacent
+
+// Error is why a call into an allocator module failed.
+pub enum Error {
+    // The module couldn't be read, compiled or instantiated.
+    Module(String),
+    // A required function isn't exported.
+    MissingExport { name: String, expected: FuncType },
+    // A function is exported with the wrong signature, or isn't a function.
+    Signature { name: String, expected: FuncType, actual: Box<ExternType> },
+    // The module's exports break the conventions in some other way.
+    Export(String),
+    // A call into the module trapped.
+    Trap(TrapInfo),
+    // The heap walk didn't move forward through the memory.
+    Walk(String),
+    // The module doesn't export its memory, so it can't be accessed directly.
+    NoMemory,
+    // An access to `len` bytes at `address` goes past the end of the memory.
+    OutOfBounds { address: u32, len: usize, size: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Module(message) | Error::Export(message) | Error::Walk(message) => write!(f, "{}", message),
+            Error::MissingExport { name, expected } => {
+                write!(f, "the module doesn't export {}{}", name, signature(expected))
+            }
+            Error::Signature { name, expected, actual } => {
+                let actual = match &**actual {
+                    ExternType::Func(ty) => format!("{}{}", name, signature(ty)),
+                    ExternType::Memory(_) => format!("a memory named {}", name),
+                    ExternType::Global(_) => format!("a global named {}", name),
+                    ExternType::Table(_) => format!("a table named {}", name),
+                };
+                write!(f, "the module exports {} instead of {}{}", actual, name, signature(expected))
+            }
+            Error::Trap(trap) => write!(f, "{}", trap),
+            Error::NoMemory => write!(f, "the module doesn't export its memory"),
+            Error::OutOfBounds { address, len, size } => {
+                write!(f, "{} bytes at {:#x} are out of bounds of the {} byte memory", len, address, size)
+            }
+        }
+    }
+}
+
+// Errors print the same for debugging, so that they read well when returned
+// from main.
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+// signature formats a function type the way the exports are documented, e.g.
+// "(i32, i32) -> i32".
+pub fn signature(ty: &FuncType) -> String {
+    let list = |types: &mut dyn ExactSizeIterator<Item = ValType>| types.map(|ty| ty.to_string()).collect::<Vec<_>>().join(", ");
+    let params = format!("({})", list(&mut ty.params()));
+    match ty.results().len() {
+        0 => params,
+        1 => format!("{} -> {}", params, list(&mut ty.results())),
+        _ => format!("{} -> ({})", params, list(&mut ty.results())),
+    }
+}
+
+// TrapInfo describes a trap: the call into the module that triggered it, why
+// it happened, and where.
+#[derive(Debug, Clone)]
+pub struct TrapInfo {
+    // operation is the call that trapped, e.g. "alloc(24)".
+    pub operation: String,
+    // code is the kind of trapping instruction, or None if the trap had
+    // another cause, such as running out of fuel.
+    pub code: Option<TrapCode>,
+    pub message: String,
+    // backtrace holds the frames of the module at the trap, innermost first.
+    pub backtrace: Vec<Frame>,
+}
+
+// Frame is a function of the module on the stack at a trap.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub function: String,
+    // offset is the position of the instruction in the module's binary.
+    pub offset: Option<usize>,
+}
+
+impl fmt::Display for TrapInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} trapped: {}", self.operation, self.message)?;
+        if !self.backtrace.is_empty() {
+            write!(f, "\nwasm backtrace:")?;
+        }
+        for (i, frame) in self.backtrace.iter().enumerate() {
+            match frame.offset {
+                Some(offset) => write!(f, "\n  {:>3}: {:#6x} - {}", i, offset, frame.function)?,
+                None => write!(f, "\n  {:>3}: {}", i, frame.function)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// trapped returns the error for a trap during `operation`.
+fn trapped(operation: String, trap: Trap) -> Error {
+    let code = trap.trap_code();
+    let message = match code {
+        Some(code) => code.to_string(),
+        None => trap.to_string().lines().next().unwrap_or("").to_string(),
+    };
+    let backtrace = trap
+        .trace()
+        .unwrap_or(&[])
+        .iter()
+        .map(|frame| Frame {
+            function: match frame.func_name() {
+                Some(name) => name.to_string(),
+                None => format!("<function {}>", frame.func_index()),
+            },
+            offset: frame.module_offset(),
+        })
+        .collect();
+    Error::Trap(TrapInfo { operation, code, message, backtrace })
+}
+
+// Int is an integer type the memory can be read and written as, in
+// little-endian byte order like WebAssembly's loads and stores.
+pub trait Int: Copy {
+    const SIZE: usize;
+    fn from_le(bytes: &[u8]) -> Self;
+    fn to_le(self) -> Vec<u8>;
+}
+
+macro_rules! int {
+    ($($ty:ty),*) => {$(
+        impl Int for $ty {
+            const SIZE: usize = std::mem::size_of::<$ty>();
+            fn from_le(bytes: &[u8]) -> $ty {
+                <$ty>::from_le_bytes(bytes.try_into().expect("wrong number of bytes"))
+            }
+            fn to_le(self) -> Vec<u8> {
+                self.to_le_bytes().to_vec()
+            }
+        }
+    )*};
+}
+
+int!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+// Block is a block of the allocator, as reported by the heap walk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Block {
+    pub address: u64,
+    pub size: u64,
+    pub free: bool,
+}
+
+// Walk holds the heap walk functions of a module.
+#[derive(Clone, Copy)]
+struct Walk {
+    first: TypedFunc<(), i32>,
+    next: TypedFunc<i32, i32>,
+    size: TypedFunc<i32, i32>,
+    is_free: TypedFunc<i32, i32>,
+    free_list: Option<(TypedFunc<(), i32>, TypedFunc<i32, i32>)>,
+    coalesces: Option<TypedFunc<(), i32>>,
+}
+
+// required looks up a function the module must export, checking that it has
+// the type given by its spec in interface::SPECS.
+fn required<Params: WasmParams, Results: WasmResults>(
+    instance: &Instance,
+    wasm_store: &mut Store<()>,
+    name: &str,
+) -> Result<TypedFunc<Params, Results>, Error> {
+    let expected = interface::spec(name).ty();
+    let func = match instance.get_export(&mut *wasm_store, name) {
+        Some(Extern::Func(func)) => func,
+        Some(other) => return Err(Error::Signature { name: name.to_string(), expected, actual: Box::new(other.ty(&*wasm_store)) }),
+        None => return Err(Error::MissingExport { name: name.to_string(), expected }),
+    };
+    let actual = func.ty(&*wasm_store);
+    if actual != expected {
+        return Err(Error::Signature { name: name.to_string(), expected, actual: Box::new(actual.into()) });
+    }
+    func.typed(&*wasm_store).map_err(|err| Error::Export(format!("{}: {}", name, err)))
+}
+
+// optional looks up a function the module may not export, which is an error
+// only if it's exported with the wrong signature.
+fn optional<Params: WasmParams, Results: WasmResults>(
+    instance: &Instance,
+    wasm_store: &mut Store<()>,
+    name: &str,
+) -> Result<Option<TypedFunc<Params, Results>>, Error> {
+    if instance.get_export(&mut *wasm_store, name).is_none() {
+        return Ok(None);
+    }
+    required(instance, wasm_store, name).map(Some)
+}
+
+// WasmAllocator owns an instantiated allocator module together with callable
+// references to the functions it exports.
+pub struct WasmAllocator {
+    wasm_store: Store<()>,
+    alloc: TypedFunc<i32, i32>,
+    dealloc: TypedFunc<i32, ()>,
+    realloc: TypedFunc<(i32, i32), i32>,
+    // The helpers are None if the module doesn't export them, in which case
+    // the memory is accessed directly.
+    store: Option<TypedFunc<(i32, i32), ()>>,
+    load: Option<TypedFunc<i32, i32>>,
+    size: Option<TypedFunc<(), i32>>,
+    grow: Option<TypedFunc<i32, i32>>,
+    // memory is the module's linear memory, if it's exported as "memory".
+    memory: Option<Memory>,
+    // walk holds the heap walk functions, if the module exports them.
+    walk: Option<Walk>,
+}
+
+impl WasmAllocator {
+    // engine returns an engine configured the way the harness needs.
+    pub fn engine() -> Result<Engine, Error> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        Engine::new(&config).map_err(|err| Error::Module(format!("{:?}", err)))
+    }
+
+    // new instantiates the WebAssembly module at `path` (.wat or .wasm) and
+    // looks up the allocator functions.
+    pub fn new(path: impl AsRef<Path>) -> Result<WasmAllocator, Error> {
+        let engine = WasmAllocator::engine()?;
+        let module = Module::from_file(&engine, path).map_err(|err| Error::Module(format!("{:?}", err)))?;
+        WasmAllocator::instantiate(&engine, &module)
+    }
+
+    // instantiate creates a fresh instance of an already compiled module, which
+    // is much faster than compiling it again. The engine must be the one
+    // returned by WasmAllocator::engine.
+    pub fn instantiate(engine: &Engine, module: &Module) -> Result<WasmAllocator, Error> {
+        // Check the exports up front, so that what's missing or wrong is
+        // reported with the type the harness expects.
+        let report = interface::validate(module);
+        report.check()?;
+        let mut wasm_store: Store<()> = Store::new(engine, ());
+        let instance = Instance::new(&mut wasm_store, module, &[]).map_err(|err| Error::Module(format!("{:?}", err)))?;
+
+        // Get callable references to the functions
+        let alloc = required(&instance, &mut wasm_store, "alloc")?;
+        let dealloc = required(&instance, &mut wasm_store, "dealloc")?;
+        let realloc = required(&instance, &mut wasm_store, "realloc")?;
+        let store = optional(&instance, &mut wasm_store, "store")?;
+        let load = optional(&instance, &mut wasm_store, "load")?;
+        let size = optional(&instance, &mut wasm_store, "size")?;
+        let grow = optional(&instance, &mut wasm_store, "grow")?;
+
+        let memory = instance.get_memory(&mut wasm_store, "memory");
+
+        let walk = if !report.can_walk() {
+            None
+        } else {
+            Some(Walk {
+                first: required(&instance, &mut wasm_store, "heap_first")?,
+                next: required(&instance, &mut wasm_store, "heap_next")?,
+                size: required(&instance, &mut wasm_store, "block_size")?,
+                is_free: required(&instance, &mut wasm_store, "block_is_free")?,
+                free_list: match (
+                    optional(&instance, &mut wasm_store, "free_first")?,
+                    optional(&instance, &mut wasm_store, "free_next")?,
+                ) {
+                    (Some(first), Some(next)) => Some((first, next)),
+                    _ => None,
+                },
+                coalesces: optional(&instance, &mut wasm_store, "heap_coalesces")?,
+            })
+        };
+
+        Ok(WasmAllocator { wasm_store, alloc, dealloc, realloc, store, load, size, grow, memory, walk })
+    }
+
+    // refuel tops the store's fuel back up to FUEL_PER_CALL.
+    fn refuel(&mut self) {
+        let remaining = self.wasm_store.consume_fuel(0).unwrap_or(0);
+        // This can only fail if fuel consumption is disabled, which it isn't.
+        let _ = self.wasm_store.add_fuel(FUEL_PER_CALL - remaining);
+    }
+
+    // fuel_consumed returns the fuel consumed by the last call, which unlike
+    // its duration is the same every time the call is made.
+    pub fn fuel_consumed(&mut self) -> u64 {
+        FUEL_PER_CALL - self.wasm_store.consume_fuel(0).unwrap_or(FUEL_PER_CALL)
+    }
+
+    // alloc allocates a block of `size` bytes and returns its address.
+    pub fn alloc(&mut self, size: i32) -> Result<i32, Error> {
+        self.refuel();
+        self.alloc.call(&mut self.wasm_store, size).map_err(|trap| trapped(format!("alloc({})", size), trap))
+    }
+
+    // dealloc deallocates the block at `address`.
+    pub fn dealloc(&mut self, address: i32) -> Result<(), Error> {
+        self.refuel();
+        self.dealloc.call(&mut self.wasm_store, address).map_err(|trap| trapped(format!("dealloc({})", address), trap))
+    }
+
+    // realloc resizes the block at `address` to `size` bytes and returns its
+    // address, which may have changed.
+    pub fn realloc(&mut self, address: i32, size: i32) -> Result<i32, Error> {
+        self.refuel();
+        self.realloc.call(&mut self.wasm_store, (address, size)).map_err(|trap| trapped(format!("realloc({}, {})", address, size), trap))
+    }
+
+    // The helpers below call the module's own functions when it exports them,
+    // and access the memory directly otherwise. The validator makes sure one
+    // of the two is exported. Direct accesses consume no fuel.
+
+    // store stores a 32-bit little-endian value.
+    pub fn store(&mut self, address: i32, value: i32) -> Result<(), Error> {
+        self.refuel();
+        match self.store {
+            Some(store) => store.call(&mut self.wasm_store, (address, value)).map_err(|trap| trapped(format!("store({}, {})", address, value), trap)),
+            None => self.write_int(address as u32, value),
+        }
+    }
+
+    // load loads a 32-bit little-endian value.
+    pub fn load(&mut self, address: i32) -> Result<i32, Error> {
+        self.refuel();
+        match self.load {
+            Some(load) => load.call(&mut self.wasm_store, address).map_err(|trap| trapped(format!("load({})", address), trap)),
+            None => self.read_int(address as u32),
+        }
+    }
+
+    // memory_pages returns the current memory size in pages.
+    pub fn memory_pages(&mut self) -> Result<u32, Error> {
+        self.refuel();
+        match self.size {
+            Some(size) => Ok(size.call(&mut self.wasm_store, ()).map_err(|trap| trapped("size()".to_string(), trap))? as u32),
+            None => Ok(self.memory.ok_or(Error::NoMemory)?.size(&self.wasm_store) as u32),
+        }
+    }
+
+    // grow grows the memory by `pages` pages and returns the previous size, or
+    // -1 if the memory couldn't be grown.
+    pub fn grow(&mut self, pages: i32) -> Result<i32, Error> {
+        self.refuel();
+        match self.grow {
+            Some(grow) => grow.call(&mut self.wasm_store, pages).map_err(|trap| trapped(format!("grow({})", pages), trap)),
+            None => {
+                let memory = self.memory.ok_or(Error::NoMemory)?;
+                Ok(memory.grow(&mut self.wasm_store, pages as u32 as u64).map_or(-1, |previous| previous as i32))
+            }
+        }
+    }
+
+    // can_walk returns whether the module exports the heap walk functions.
+    pub fn can_walk(&self) -> bool {
+        self.walk.is_some()
+    }
+
+    // walk returns every block of the allocator in address order, or None if
+    // the module doesn't export the heap walk functions. A walk that doesn't
+    // move forward through the memory is an error, so that a broken allocator
+    // can't make it loop forever.
+    pub fn walk(&mut self) -> Result<Option<Vec<Block>>, Error> {
+        let walk = match self.walk {
+            Some(walk) => walk,
+            None => return Ok(None),
+        };
+        let mut blocks = Vec::new();
+        self.refuel();
+        let mut address = walk.first.call(&mut self.wasm_store, ()).map_err(|trap| trapped("heap_first()".to_string(), trap))?;
+        while address != 0 {
+            if let Some(last) = blocks.last().filter(|last: &&Block| address as u32 as u64 <= last.address) {
+                return Err(Error::Walk(format!("heap walk went from block {:#x} back to {:#x}", last.address, address as u32)));
+            }
+            self.refuel();
+            let size = walk.size.call(&mut self.wasm_store, address).map_err(|trap| trapped(format!("block_size({})", address), trap))?;
+            self.refuel();
+            let free = walk.is_free.call(&mut self.wasm_store, address).map_err(|trap| trapped(format!("block_is_free({})", address), trap))? != 0;
+            blocks.push(Block { address: address as u32 as u64, size: size as u32 as u64, free });
+            self.refuel();
+            address = walk.next.call(&mut self.wasm_store, address).map_err(|trap| trapped(format!("heap_next({})", address), trap))?;
+        }
+        Ok(Some(blocks))
+    }
+
+    // free_list returns the blocks of the allocator's free list in list order,
+    // or None if the module doesn't export it. The walk stops at the first
+    // block seen twice, which is returned last so that loops can be reported.
+    pub fn free_list(&mut self) -> Result<Option<Vec<u64>>, Error> {
+        let (first, next) = match self.walk.and_then(|walk| walk.free_list) {
+            Some(functions) => functions,
+            None => return Ok(None),
+        };
+        let mut blocks = Vec::new();
+        let mut seen = HashSet::new();
+        self.refuel();
+        let mut address = first.call(&mut self.wasm_store, ()).map_err(|trap| trapped("free_first()".to_string(), trap))?;
+        while address != 0 {
+            blocks.push(address as u32 as u64);
+            if !seen.insert(address) {
+                break;
+            }
+            self.refuel();
+            address = next.call(&mut self.wasm_store, address).map_err(|trap| trapped(format!("free_next({})", address), trap))?;
+        }
+        Ok(Some(blocks))
+    }
+
+    // coalesces returns whether the allocator says it coalesces adjacent free
+    // blocks, which it doesn't unless it exports heap_coalesces.
+    pub fn coalesces(&mut self) -> Result<bool, Error> {
+        match self.walk.and_then(|walk| walk.coalesces) {
+            Some(coalesces) => {
+                self.refuel();
+                Ok(coalesces.call(&mut self.wasm_store, ()).map_err(|trap| trapped("heap_coalesces()".to_string(), trap))? != 0)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // memory returns the contents of the linear memory, if it's exported.
+    pub fn memory(&self) -> Option<&[u8]> {
+        self.memory.map(|memory| memory.data(&self.wasm_store))
+    }
+
+    // memory_mut returns the contents of the linear memory for writing, if
+    // it's exported.
+    pub fn memory_mut(&mut self) -> Option<&mut [u8]> {
+        self.memory.map(|memory| memory.data_mut(&mut self.wasm_store))
+    }
+
+    // span returns the range of `len` bytes at `address` in the memory.
+    fn span(&self, address: u32, len: usize) -> Result<(Memory, std::ops::Range<usize>), Error> {
+        let memory = self.memory.ok_or(Error::NoMemory)?;
+        let size = memory.data_size(&self.wasm_store);
+        let start = address as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= size => Ok((memory, start..end)),
+            _ => Err(Error::OutOfBounds { address, len, size }),
+        }
+    }
+
+    // read_bytes copies `len` bytes at `address` out of the memory.
+    pub fn read_bytes(&self, address: u32, len: usize) -> Result<Vec<u8>, Error> {
+        let (memory, range) = self.span(address, len)?;
+        Ok(memory.data(&self.wasm_store)[range].to_vec())
+    }
+
+    // write_bytes copies the bytes into the memory at `address`.
+    pub fn write_bytes(&mut self, address: u32, bytes: &[u8]) -> Result<(), Error> {
+        let (memory, range) = self.span(address, bytes.len())?;
+        memory.data_mut(&mut self.wasm_store)[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    // read_int reads an integer at `address` out of the memory, e.g.
+    // `read_int::<u16>(address)`. Unlike load, it doesn't go through the
+    // module and works at any alignment.
+    pub fn read_int<T: Int>(&self, address: u32) -> Result<T, Error> {
+        Ok(T::from_le(&self.read_bytes(address, T::SIZE)?))
+    }
+
+    // write_int writes an integer into the memory at `address`.
+    pub fn write_int<T: Int>(&mut self, address: u32, value: T) -> Result<(), Error> {
+        self.write_bytes(address, &value.to_le())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // allocator instantiates a module given as text.
+    fn allocator(wat: &str) -> Result<WasmAllocator, Error> {
+        let engine = WasmAllocator::engine()?;
+        let module = Module::new(&engine, wat).map_err(|err| Error::Module(format!("{:?}", err)))?;
+        WasmAllocator::instantiate(&engine, &module)
+    }
+
+    // module returns a module with an exported memory and the given alloc body.
+    fn module(alloc: &str) -> String {
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (func $alloc (export "alloc") (param i32) (result i32) {})
+                (func (export "dealloc") (param i32))
+                (func (export "realloc") (param i32 i32) (result i32) (i32.const 0)))"#,
+            alloc
+        )
+    }
+
+    #[test]
+    fn traps() {
+        let err = allocator(&module("(unreachable)")).unwrap().alloc(8).unwrap_err();
+        match &err {
+            Error::Trap(trap) => {
+                assert_eq!(trap.operation, "alloc(8)");
+                assert_eq!(trap.code, Some(TrapCode::UnreachableCodeReached));
+                assert_eq!(trap.backtrace[0].function, "alloc");
+            }
+            other => panic!("expected a trap, got {}", other),
+        }
+        assert!(err.to_string().starts_with("alloc(8) trapped: wasm `unreachable` instruction executed\nwasm backtrace:\n    0: "));
+    }
+
+    #[test]
+    fn running_out_of_fuel_traps_without_a_code() {
+        match allocator(&module("(loop $loop (br $loop)) (i32.const 0)")).unwrap().alloc(8) {
+            Err(Error::Trap(trap)) => assert_eq!(trap.code, None),
+            other => panic!("expected a trap, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn export_errors() {
+        let missing = allocator(r#"(module (memory (export "memory") 1) (func (export "alloc") (param i32) (result i32) (i32.const 0)))"#);
+        assert_eq!(missing.err().unwrap().to_string(), "the module doesn't export dealloc(i32)");
+        let wrong = module("(i32.const 0)").replace(r#"(func (export "dealloc") (param i32))"#, r#"(func (export "dealloc") (param i64))"#);
+        assert_eq!(allocator(&wrong).err().unwrap().to_string(), "the module exports dealloc(i64) instead of dealloc(i32)");
+        assert!(matches!(WasmAllocator::new("missing.wat"), Err(Error::Module(_))));
+    }
+
+    #[test]
+    fn memory_errors() {
+        let mut allocator = allocator(&module("(i32.const 0)")).unwrap();
+        assert_eq!(allocator.read_bytes(65530, 6).unwrap().len(), 6);
+        let err = allocator.read_bytes(65530, 8).unwrap_err();
+        assert!(matches!(err, Error::OutOfBounds { address: 65530, len: 8, size: 65536 }));
+        assert_eq!(err.to_string(), "8 bytes at 0xfffa are out of bounds of the 65536 byte memory");
+        assert!(matches!(allocator.write_bytes(u32::MAX, &[0]), Err(Error::OutOfBounds { .. })));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::ops::PAGE_SIZE;
+use crate::{bench, fuel, metrics, workload};
+use std::error::Error;
+use std::fs;
+
+// A baseline records the metrics of workloads run against modules, so that
+// later runs can be checked against it for regressions. The file has a header
+// line, the seed used to generate preset workloads, and one metric per line:
+//
+//     wasmalloc-baseline 1
+//     seed 1
+//     src/1_minimal.wat stack fuel.alloc 17
+//     src/1_minimal.wat stack pages 40
+//
+// Fuel and heap metrics are deterministic. Timing metrics are only recorded
+// when asked to, as they vary from run to run.
+
+const HEADER: &str = "wasmalloc-baseline 1";
+
+// Entry is a metric of a workload run against a module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub module: String,
+    pub workload: String,
+    pub metric: String,
+    pub value: f64,
+}
+
+// higher_is_better returns whether an increase of the metric is an
+// improvement rather than a regression.
+fn higher_is_better(metric: &str) -> bool {
+    metric == "utilization" || metric == "time.throughput"
+}
+
+// Timing sets the iterations used to measure timing metrics.
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    pub iterations: usize,
+    pub warmup: usize,
+}
+
+// measure runs the workload against the module and returns its metrics.
+pub fn measure(module: &str, name: &str, seed: u64, timing: Option<Timing>) -> Result<Vec<(String, f64)>, Box<dyn Error>> {
+    let ops = workload::load(name, seed)?;
+    let mut values = Vec::new();
+
+    let fuel = fuel::run(module, name, &ops, 0)?;
+    for (kind, histogram) in bench::OPERATIONS.iter().zip(&fuel.histograms) {
+        if histogram.count > 0 {
+            values.push((format!("fuel.{}", kind), histogram.sum as f64 / histogram.count as f64));
+        }
+    }
+    let total = fuel.total();
+    values.push(("fuel.call".to_string(), total.sum as f64 / total.count.max(1) as f64));
+    values.push(("fuel.total".to_string(), total.sum as f64));
+    values.push(("fuel.max".to_string(), total.max as f64));
+
+    let heap = metrics::run(module, name, &ops, ops.len().div_ceil(20))?.summary;
+    values.push(("pages".to_string(), (heap.peak_memory / PAGE_SIZE) as f64));
+    values.push(("utilization".to_string(), heap.utilization()));
+    values.push(("fragmentation.mean".to_string(), heap.mean_fragmentation));
+    values.push(("fragmentation.max".to_string(), heap.max_fragmentation));
+
+    if let Some(timing) = timing {
+        let total = bench::run(module, name, &ops, timing.iterations, timing.warmup)?.total();
+        values.push(("time.p50".to_string(), total.percentile(50.0) as f64));
+        values.push(("time.p99".to_string(), total.percentile(99.0) as f64));
+        values.push(("time.throughput".to_string(), total.throughput()));
+    }
+    Ok(values)
+}
+
+// save writes a baseline file.
+pub fn save(path: &str, seed: u64, entries: &[Entry]) -> Result<(), Box<dyn Error>> {
+    let mut text = format!("{}\nseed {}\n", HEADER, seed);
+    for entry in entries {
+        text += &format!("{} {} {} {}\n", entry.module, entry.workload, entry.metric, entry.value);
+    }
+    fs::write(path, text).map_err(|err| format!("{}: {}", path, err))?;
+    Ok(())
+}
+
+// load reads a baseline file and returns its seed and entries.
+pub fn load(path: &str) -> Result<(u64, Vec<Entry>), Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    let mut lines = text.lines().enumerate();
+    if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+        return Err(format!("{}: missing '{}' header", path, HEADER).into());
+    }
+    let mut seed = 1;
+    let mut entries = Vec::new();
+    for (i, line) in lines {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let invalid = || format!("{}:{}: invalid line '{}'", path, i + 1, line);
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [] => {}
+            ["seed", value] => seed = value.parse().map_err(|_| invalid())?,
+            [module, workload, metric, value] => entries.push(Entry {
+                module: module.to_string(),
+                workload: workload.to_string(),
+                metric: metric.to_string(),
+                value: value.parse().map_err(|_| invalid())?,
+            }),
+            _ => return Err(invalid().into()),
+        }
+    }
+    Ok((seed, entries))
+}
+
+// Thresholds holds the allowed worsening of metrics, in percent. Each rule
+// applies to the metrics starting with its prefix, and the longest matching
+// prefix wins. An empty prefix matches every metric.
+#[derive(Debug, Clone)]
+pub struct Thresholds {
+    rules: Vec<(String, f64)>,
+}
+
+impl Thresholds {
+    // parse parses rules such as `fuel=0`, `time=25` or a bare `5`.
+    pub fn parse(rules: &[String]) -> Result<Thresholds, String> {
+        // Deterministic metrics must not get worse at all by default.
+        let mut parsed = vec![(String::new(), 0.0), ("time".to_string(), 25.0)];
+        for rule in rules {
+            let (prefix, percent) = rule.split_once('=').unwrap_or(("", rule));
+            let percent = percent.parse().map_err(|_| format!("invalid threshold '{}'", rule))?;
+            parsed.retain(|(existing, _)| existing != prefix);
+            parsed.push((prefix.to_string(), percent));
+        }
+        Ok(Thresholds { rules: parsed })
+    }
+
+    // percent returns the allowed worsening of the metric in percent.
+    pub fn percent(&self, metric: &str) -> f64 {
+        self.rules
+            .iter()
+            .filter(|(prefix, _)| metric.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(0.0, |(_, percent)| *percent)
+    }
+}
+
+// Change is a metric of the baseline compared with the current run.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub baseline: Entry,
+    // current is the current value, or None if it couldn't be measured.
+    pub current: Option<f64>,
+    pub regressed: bool,
+}
+
+impl Change {
+    // percent returns the relative change from the baseline value.
+    pub fn percent(&self) -> Option<f64> {
+        let current = self.current?;
+        if self.baseline.value == 0.0 {
+            return Some(if current == 0.0 { 0.0 } else { f64::INFINITY.copysign(current) });
+        }
+        Some((current - self.baseline.value) / self.baseline.value.abs() * 100.0)
+    }
+}
+
+// compare compares the current metrics with the baseline entries of the same
+// module and workload.
+pub fn compare(baseline: &[Entry], current: &[(String, f64)], thresholds: &Thresholds) -> Vec<Change> {
+    baseline
+        .iter()
+        .map(|entry| {
+            let value = current.iter().find(|(metric, _)| *metric == entry.metric).map(|(_, value)| *value);
+            let mut change = Change { baseline: entry.clone(), current: value, regressed: value.is_none() };
+            if let Some(percent) = change.percent() {
+                let worse = if higher_is_better(&entry.metric) { -percent } else { percent };
+                change.regressed = worse > thresholds.percent(&entry.metric) + 1e-9;
+            }
+            change
+        })
+        .collect()
+}
+
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:.4}", value)
+    }
+}
+
+// print_changes prints the comparison as a table.
+pub fn print_changes(changes: &[Change]) {
+    let width = |f: fn(&Change) -> usize| changes.iter().map(f).max().unwrap_or(0);
+    let module_width = width(|change| change.baseline.module.len()).max(6);
+    let workload_width = width(|change| change.baseline.workload.len()).max(8);
+    println!(
+        "{:mw$} {:ww$} {:18} {:>14} {:>14} {:>9}",
+        "module",
+        "workload",
+        "metric",
+        "baseline",
+        "current",
+        "change",
+        mw = module_width,
+        ww = workload_width
+    );
+    for change in changes {
+        let current = change.current.map_or("failed".to_string(), format_value);
+        let percent = change.percent().map_or(String::new(), |percent| format!("{:+.1}%", percent));
+        println!(
+            "{:mw$} {:ww$} {:18} {:>14} {:>14} {:>9}{}",
+            change.baseline.module,
+            change.baseline.workload,
+            change.baseline.metric,
+            format_value(change.baseline.value),
+            current,
+            percent,
+            if change.regressed { "  REGRESSION" } else { "" },
+            mw = module_width,
+            ww = workload_width
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use crate::allocator::WasmAllocator;
+use crate::json;
+use crate::ops::{Op, Runner};
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+use wasmtime::Module;
+
+// Benchmarking replays a workload against fresh instances of a module, timing
+// every alloc, dealloc and realloc call, and reports the throughput and the
+// latency distribution of each kind of call. Warmup iterations run first and
+// aren't measured. Calls are timed with fuel metering enabled, like everywhere
+// else in the harness, so the numbers are comparable between allocators but
+// higher than they'd be without it.
+
+// SUB_BUCKETS is the number of buckets per power of two in a histogram, which
+// bounds the error of a recorded value to 1/SUB_BUCKETS, under 1%.
+const SUB_BUCKETS: u64 = 128;
+const SUB_BUCKET_BITS: u32 = 7;
+
+// Histogram counts values (latencies in nanoseconds, or fuel) in buckets that
+// are one apart for small values and get wider for larger ones, like
+// HdrHistogram, so it has a fixed relative precision and a small fixed size.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    pub count: u64,
+    pub sum: u64,
+    pub max: u64,
+}
+
+// bucket returns the index of the bucket holding the value. Values below
+// 2 * SUB_BUCKETS get a bucket each. Larger values keep their top
+// SUB_BUCKET_BITS + 1 bits, so each power of two is split into SUB_BUCKETS
+// buckets.
+fn bucket(value: u64) -> usize {
+    let magnitude = (64 - value.leading_zeros()).saturating_sub(SUB_BUCKET_BITS + 1);
+    (magnitude as u64 * SUB_BUCKETS + (value >> magnitude)) as usize
+}
+
+// bucket_value returns the highest value that falls in the bucket.
+fn bucket_value(index: usize) -> u64 {
+    let index = index as u64;
+    if index < 2 * SUB_BUCKETS {
+        return index;
+    }
+    let magnitude = (index - SUB_BUCKETS) / SUB_BUCKETS;
+    let sub = index - magnitude * SUB_BUCKETS;
+    ((sub + 1) << magnitude) - 1
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram::default()
+    }
+
+    pub fn record(&mut self, value: u64) {
+        let index = bucket(value);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+        self.count += 1;
+        self.sum += value;
+        self.max = self.max.max(value);
+    }
+
+    // percentile returns the value below which `percent` percent of the
+    // recorded values fall, or zero if nothing was recorded.
+    pub fn percentile(&self, percent: f64) -> u64 {
+        let rank = ((percent / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_value(index).min(self.max);
+            }
+        }
+        0
+    }
+
+    // merge adds the values recorded in another histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (index, count) in other.counts.iter().enumerate() {
+            self.counts[index] += count;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+    }
+
+    // throughput returns the number of calls per second spent in the calls.
+    pub fn throughput(&self) -> f64 {
+        if self.sum == 0 {
+            return 0.0;
+        }
+        self.count as f64 * 1e9 / self.sum as f64
+    }
+}
+
+// format_duration formats nanoseconds with a readable unit.
+pub fn format_duration(nanos: u64) -> String {
+    match nanos {
+        0..=999 => format!("{}ns", nanos),
+        1_000..=999_999 => format!("{:.1}µs", nanos as f64 / 1e3),
+        _ => format!("{:.1}ms", nanos as f64 / 1e6),
+    }
+}
+
+// format_rate formats a number of calls per second.
+pub fn format_rate(rate: f64) -> String {
+    if rate >= 1e6 {
+        format!("{:.2}M/s", rate / 1e6)
+    } else if rate >= 1e3 {
+        format!("{:.1}k/s", rate / 1e3)
+    } else {
+        format!("{:.0}/s", rate)
+    }
+}
+
+// OPERATIONS names the timed calls, in the order of Report::histograms.
+pub const OPERATIONS: [&str; 3] = ["alloc", "dealloc", "realloc"];
+
+// Report holds the results of benchmarking a workload against a module.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub module: String,
+    pub workload: String,
+    pub iterations: usize,
+    pub warmup: usize,
+    // histograms holds the latencies of each call, as in OPERATIONS.
+    pub histograms: [Histogram; 3],
+    // elapsed is the total time of the measured iterations, including the
+    // time spent outside of the calls.
+    pub elapsed: Duration,
+}
+
+impl Report {
+    // total returns the latencies of all calls together.
+    pub fn total(&self) -> Histogram {
+        let mut total = Histogram::new();
+        for histogram in &self.histograms {
+            total.merge(histogram);
+        }
+        total
+    }
+
+    // rows returns the name and latencies of each kind of call that was made,
+    // followed by all calls together.
+    fn rows(&self) -> Vec<(&str, Histogram)> {
+        let mut rows: Vec<(&str, Histogram)> = OPERATIONS
+            .iter()
+            .zip(&self.histograms)
+            .filter(|(_, histogram)| histogram.count > 0)
+            .map(|(name, histogram)| (*name, histogram.clone()))
+            .collect();
+        rows.push(("total", self.total()));
+        rows
+    }
+
+    // to_json encodes the report as a JSON object, with latencies in
+    // nanoseconds and throughputs in calls per second.
+    pub fn to_json(&self) -> String {
+        let operations: Vec<String> = self
+            .rows()
+            .iter()
+            .map(|(name, histogram)| {
+                format!(
+                    "{}: {{\"count\": {}, \"throughput\": {:.0}, \"p50\": {}, \"p90\": {}, \"p99\": {}, \"max\": {}}}",
+                    json::string(name),
+                    histogram.count,
+                    histogram.throughput(),
+                    histogram.percentile(50.0),
+                    histogram.percentile(90.0),
+                    histogram.percentile(99.0),
+                    histogram.max
+                )
+            })
+            .collect();
+        format!(
+            "{{\"module\": {}, \"workload\": {}, \"iterations\": {}, \"warmup\": {}, \"elapsed\": {}, \"operations\": {{{}}}}}",
+            json::string(&self.module),
+            json::string(&self.workload),
+            self.iterations,
+            self.warmup,
+            self.elapsed.as_nanos(),
+            operations.join(", ")
+        )
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "module:     {}", self.module)?;
+        writeln!(f, "workload:   {}", self.workload)?;
+        writeln!(f, "iterations: {} (after {} warmup)", self.iterations, self.warmup)?;
+        writeln!(f, "elapsed:    {}", format_duration(self.elapsed.as_nanos() as u64))?;
+        writeln!(f)?;
+        write!(f, "{:8} {:>9} {:>10} {:>8} {:>8} {:>8} {:>8}", "op", "count", "throughput", "p50", "p90", "p99", "max")?;
+        for (name, histogram) in self.rows() {
+            write!(
+                f,
+                "\n{:8} {:>9} {:>10} {:>8} {:>8} {:>8} {:>8}",
+                name,
+                histogram.count,
+                format_rate(histogram.throughput()),
+                format_duration(histogram.percentile(50.0)),
+                format_duration(histogram.percentile(90.0)),
+                format_duration(histogram.percentile(99.0)),
+                format_duration(histogram.max)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Call is a timed allocator call made while running a workload.
+#[derive(Debug, Clone, Copy)]
+pub struct Call {
+    // index is the index of the operation in the workload.
+    pub index: usize,
+    // kind is the index of the call in OPERATIONS.
+    pub kind: usize,
+    pub nanos: u64,
+    // fuel is the fuel the call consumed.
+    pub fuel: u64,
+}
+
+// run_once replays the operations against a fresh instance, passing every
+// alloc, dealloc and realloc call to `record`. Blocks that don't fit in the
+// memory are treated as failed allocations, as in replay. Stores and loads
+// aren't measured, so they're skipped.
+pub fn run_once(allocator: WasmAllocator, ops: &[Op], mut record: impl FnMut(Call)) -> Result<(), String> {
+    let mut runner = Runner::new(allocator);
+    for (index, op) in ops.iter().enumerate() {
+        let kind = match op {
+            Op::Alloc { .. } => 0,
+            Op::Dealloc { .. } => 1,
+            Op::Realloc { .. } => 2,
+            Op::Store { .. } | Op::Load { .. } => continue,
+        };
+        runner.step(index, op)?;
+        if let Some(timing) = runner.timing {
+            record(Call { index, kind, nanos: timing.nanos, fuel: timing.fuel });
+        }
+    }
+    Ok(())
+}
+
+// run benchmarks the operations against the module at `path`.
+pub fn run(path: &str, workload: &str, ops: &[Op], iterations: usize, warmup: usize) -> Result<Report, Box<dyn Error>> {
+    let engine = WasmAllocator::engine()?;
+    let module = Module::from_file(&engine, path)?;
+    let mut histograms: [Histogram; 3] = Default::default();
+    for i in 0..warmup {
+        let allocator = WasmAllocator::instantiate(&engine, &module)?;
+        run_once(allocator, ops, |_| {}).map_err(|err| format!("warmup {}: {}", i + 1, err))?;
+    }
+    let mut elapsed = Duration::ZERO;
+    for i in 0..iterations {
+        let allocator = WasmAllocator::instantiate(&engine, &module)?;
+        let start = Instant::now();
+        run_once(allocator, ops, |call| histograms[call.kind].record(call.nanos)).map_err(|err| format!("iteration {}: {}", i + 1, err))?;
+        elapsed += start.elapsed();
+    }
+    Ok(Report {
+        module: path.to_string(),
+        workload: workload.to_string(),
+        iterations,
+        warmup,
+        histograms,
+        elapsed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::{Rng, Source};
+
+    // check_percentiles records the values and checks every percentile
+    // against the exact one, within the histogram's relative error.
+    fn check_percentiles(mut values: Vec<u64>) {
+        let mut histogram = Histogram::new();
+        for &value in &values {
+            histogram.record(value);
+        }
+        values.sort();
+        for percent in [1.0, 10.0, 25.0, 50.0, 75.0, 90.0, 99.0, 99.9, 100.0] {
+            let rank = ((percent / 100.0 * values.len() as f64).ceil() as usize).max(1);
+            let exact = values[rank - 1];
+            let estimate = histogram.percentile(percent);
+            assert!(estimate >= exact, "p{}: {} is below the exact {}", percent, estimate, exact);
+            let error = (estimate - exact) as f64 / exact.max(1) as f64;
+            assert!(error < 1.0 / SUB_BUCKETS as f64, "p{}: {} is {:.3}% off the exact {}", percent, estimate, error * 100.0, exact);
+        }
+    }
+
+    #[test]
+    fn buckets_cover_every_value_once() {
+        let mut last = 0;
+        for value in (0..100_000).chain([u32::MAX as u64, u64::MAX / 3]) {
+            let index = bucket(value);
+            assert!(bucket_value(index) >= value);
+            assert!(index == 0 || bucket_value(index - 1) < value);
+            assert!(index >= last);
+            last = index;
+        }
+    }
+
+    #[test]
+    fn percentiles_of_uniform_values() {
+        check_percentiles((1..=100_000).collect());
+    }
+
+    #[test]
+    fn percentiles_of_exponential_values() {
+        // Latency-like values spanning several powers of two.
+        let mut rng = Rng::new(1);
+        let values = (0..50_000).map(|_| (-(1.0 - rng.fraction()).ln() * 2000.0) as u64 + 100).collect();
+        check_percentiles(values);
+    }
+
+    #[test]
+    fn small_values_are_exact() {
+        check_percentiles((0..256).collect());
+        assert_eq!(Histogram::new().percentile(50.0), 0);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::shadow::{Violation, ViolationKind};
+use std::collections::BTreeMap;
+
+// Canary fills every allocated block with a known pattern by writing directly
+// into the exported memory, and keeps a copy of what each live block should
+// contain. Checking the copies against the memory after each allocator call
+// catches the allocator writing into live blocks, e.g. block headers or
+// free-list pointers clobbering a neighbor, and realloc losing data.
+#[derive(Default)]
+pub struct Canary {
+    // blocks maps the addresses of live blocks to their expected contents.
+    blocks: BTreeMap<u32, Vec<u8>>,
+}
+
+// pattern returns the byte the canary writes at `offset` in a block. It varies
+// with the offset so that data copied to the wrong place doesn't match.
+fn pattern(offset: usize) -> u8 {
+    (offset as u8).wrapping_mul(0x3B) ^ 0xA5
+}
+
+// span returns the byte range of the block that lies inside the memory.
+fn span(memory: &[u8], address: i32, size: usize) -> (usize, usize) {
+    let start = (address as u32 as usize).min(memory.len());
+    (start, start.saturating_add(size).min(memory.len()))
+}
+
+impl Canary {
+    pub fn new() -> Canary {
+        Canary::default()
+    }
+
+    // alloc fills a block returned by alloc with the pattern.
+    pub fn alloc(&mut self, memory: &mut [u8], address: i32, size: i32) {
+        if size <= 0 {
+            return;
+        }
+        let (start, end) = span(memory, address, size as usize);
+        for (offset, byte) in memory[start..end].iter_mut().enumerate() {
+            *byte = pattern(offset);
+        }
+        self.blocks.insert(address as u32, memory[start..end].to_vec());
+    }
+
+    // dealloc stops watching a block. It must be checked before deallocating.
+    pub fn dealloc(&mut self, address: i32) {
+        self.blocks.remove(&(address as u32));
+    }
+
+    // realloc checks that the block moved by realloc kept its contents up to
+    // the smaller of both sizes, and fills the rest with the pattern.
+    pub fn realloc(&mut self, index: usize, memory: &mut [u8], address: i32, size: i32, new: i32) -> Vec<Violation> {
+        let old = match self.blocks.remove(&(address as u32)) {
+            Some(old) => old,
+            // The block isn't watched, so treat it as a fresh allocation.
+            None => {
+                self.alloc(memory, new, size);
+                return Vec::new();
+            }
+        };
+        let (start, end) = span(memory, new, size.max(0) as usize);
+        let kept = old.len().min(end - start);
+        let mut violations = Vec::new();
+        if let Some(offset) = (0..kept).find(|&i| memory[start + i] != old[i]) {
+            violations.push(Violation {
+                index,
+                kind: ViolationKind::Corruption,
+                message: format!(
+                    "realloc({}, {}) = {} didn't preserve the contents: byte at offset {} is {:#04x} instead of {:#04x}",
+                    address,
+                    size,
+                    new,
+                    offset,
+                    memory[start + offset],
+                    old[offset]
+                ),
+                blocks: Vec::new(),
+            });
+        }
+        // Watch the block as it is, so one mistake isn't reported repeatedly.
+        for (offset, byte) in memory[start + kept..end].iter_mut().enumerate() {
+            *byte = pattern(kept + offset);
+        }
+        self.blocks.insert(new as u32, memory[start..end].to_vec());
+        violations
+    }
+
+    // store records that the host stored `bytes` at the address, updating the
+    // expected contents of the blocks it touches.
+    pub fn store(&mut self, address: i32, bytes: &[u8]) {
+        let start = address as u32 as usize;
+        // Blocks starting up to the end of the write may overlap it. The end
+        // can be past u32::MAX, where no block starts.
+        let end = (start as u64 + bytes.len() as u64).min(u32::MAX as u64) as u32;
+        for (&block, expected) in self.blocks.range_mut(..=end) {
+            for (i, &byte) in bytes.iter().enumerate() {
+                if let Some(offset) = (start + i).checked_sub(block as usize) {
+                    if let Some(slot) = expected.get_mut(offset) {
+                        *slot = byte;
+                    }
+                }
+            }
+        }
+    }
+
+    // check compares one live block against its expected contents.
+    pub fn check(&mut self, index: usize, memory: &[u8], address: i32, after: &str) -> Option<Violation> {
+        let expected = self.blocks.get_mut(&(address as u32))?;
+        let (start, end) = span(memory, address, expected.len());
+        let actual = &memory[start..end];
+        let offset = (0..expected.len()).find(|&i| actual.get(i) != Some(&expected[i]))?;
+        let message = format!(
+            "block at {} was modified at offset {} after {}: {}",
+            address,
+            offset,
+            after,
+            describe_change(&expected[offset..], &actual[offset.min(actual.len())..])
+        );
+        // Accept the new contents so the same corruption isn't reported again.
+        expected.truncate(actual.len());
+        expected.copy_from_slice(actual);
+        Some(Violation { index, kind: ViolationKind::Corruption, message, blocks: Vec::new() })
+    }
+
+    // check_all compares every live block against its expected contents.
+    pub fn check_all(&mut self, index: usize, memory: &[u8], after: &str) -> Vec<Violation> {
+        let addresses: Vec<u32> = self.blocks.keys().copied().collect();
+        addresses
+            .into_iter()
+            .filter_map(|address| self.check(index, memory, address as i32, after))
+            .collect()
+    }
+}
+
+// describe_change shows the first few bytes that differ, as found vs expected.
+fn describe_change(expected: &[u8], actual: &[u8]) -> String {
+    let n = expected.len().min(8);
+    format!("found {:02x?}, expected {:02x?}", &actual[..n.min(actual.len())], &expected[..n])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_finds_a_clobbered_byte() {
+        let mut memory = vec![0; 64];
+        let mut canary = Canary::new();
+        canary.alloc(&mut memory, 8, 12);
+        canary.alloc(&mut memory, 24, 8);
+        assert!(canary.check_all(0, &memory, "alloc(8)").is_empty());
+        memory[13] ^= 0xFF;
+        let violations = canary.check_all(1, &memory, "alloc(4) = 20");
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].kind, ViolationKind::Corruption);
+        assert!(violations[0].message.starts_with("block at 8 was modified at offset 5 after alloc(4) = 20: found ["));
+        // The new contents are accepted, so the change is only reported once.
+        assert!(canary.check(2, &memory, 8, "its last use").is_none());
+    }
+
+    #[test]
+    fn stores_by_the_host_are_expected() {
+        let mut memory = vec![0; 64];
+        let mut canary = Canary::new();
+        canary.alloc(&mut memory, 8, 12);
+        memory[16..20].copy_from_slice(&42i32.to_le_bytes());
+        canary.store(16, &42i32.to_le_bytes());
+        assert!(canary.check_all(0, &memory, "store").is_empty());
+    }
+
+    #[test]
+    fn stores_at_the_end_of_the_address_space() {
+        let mut canary = Canary::new();
+        canary.blocks.insert(0xFFFF_FFF0, vec![0; 16]);
+        canary.store(0xFFFF_FFFC_u32 as i32, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(canary.blocks[&0xFFFF_FFF0][12..], [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fmt;
+
+// Operand is an argument to a command. It's either a literal number or a
+// reference to a named handle, optionally with a byte offset (e.g. `$a+4`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Literal(i32),
+    Handle(String, i32),
+}
+
+impl Operand {
+    // resolve returns the value of the operand, looking up handles by name.
+    pub fn resolve(&self, handles: &HashMap<String, i32>) -> Result<i32, String> {
+        match self {
+            Operand::Literal(value) => Ok(*value),
+            Operand::Handle(name, offset) => handles
+                .get(name)
+                .map(|address| address.wrapping_add(*offset))
+                .ok_or_else(|| format!("unknown handle ${}", name)),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Literal(value) => write!(f, "{}", value),
+            Operand::Handle(name, 0) => write!(f, "${}", name),
+            Operand::Handle(name, offset) => write!(f, "${}{:+}", name, offset),
+        }
+    }
+}
+
+// Command is a single operation on an allocator module.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Alloc(Operand),
+    Dealloc(Operand),
+    Realloc(Operand, Operand),
+    Store(Operand, Operand),
+    Load(Operand),
+    Size,
+    Grow(Operand),
+    // The commands below access the memory directly rather than through the
+    // module. Integers are 8, 16 or 32 bits wide, in little-endian order.
+    Read(Operand, Operand),
+    Write(Operand, Vec<Operand>),
+    ReadInt(u32, Operand),
+    WriteInt(u32, Operand, Operand),
+    // Dump reads the memory like Read, annotated with the known blocks.
+    Dump(Operand, Operand),
+}
+
+// Statement is a command with an optional handle name to bind its result to,
+// written as `$name = <command>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub bind: Option<String>,
+    pub command: Command,
+}
+
+// parse_number parses a decimal or `0x`-prefixed hexadecimal number, with an
+// optional leading minus sign.
+pub fn parse_number(token: &str) -> Result<i32, String> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, token),
+    };
+    // Parse as i64 so that the full i32 and u32 ranges are accepted, e.g.
+    // both -1 and 0xFFFFFFFF.
+    let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse::<i64>(),
+    }
+    .map_err(|_| format!("invalid number '{}'", token))?;
+    let value = if negative { -value } else { value };
+    if value < i32::MIN as i64 || value > u32::MAX as i64 {
+        return Err(format!("number '{}' doesn't fit in 32 bits", token));
+    }
+    Ok(value as i32)
+}
+
+// parse_handle_name checks that `name` is a valid handle name: a non-empty
+// sequence of alphanumeric characters and underscores.
+fn parse_handle_name(name: &str) -> Result<String, String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("invalid handle name '${}'", name));
+    }
+    Ok(name.to_string())
+}
+
+// parse_operand parses a number or a handle reference such as `$a`, `$a+8`
+// or `$a-4`.
+pub fn parse_operand(token: &str) -> Result<Operand, String> {
+    match token.strip_prefix('$') {
+        Some(rest) => match rest.find(['+', '-']) {
+            Some(i) => {
+                let offset = parse_number(rest[i + 1..].trim())?;
+                let offset = if &rest[i..i + 1] == "-" { offset.wrapping_neg() } else { offset };
+                Ok(Operand::Handle(parse_handle_name(&rest[..i])?, offset))
+            }
+            None => Ok(Operand::Handle(parse_handle_name(rest)?, 0)),
+        },
+        None => Ok(Operand::Literal(parse_number(token)?)),
+    }
+}
+
+// parse_command parses a command name and its arguments.
+pub fn parse_command(name: &str, args: &[&str]) -> Result<Command, String> {
+    // write takes any number of bytes after the address.
+    if name == "write" {
+        let (address, bytes) = args.split_first().filter(|(_, bytes)| !bytes.is_empty()).ok_or("'write' takes an address and at least one byte")?;
+        let bytes = bytes.iter().map(|byte| parse_operand(byte)).collect::<Result<_, _>>()?;
+        return Ok(Command::Write(parse_operand(address)?, bytes));
+    }
+    // Check the number of arguments up front so each arm can index freely.
+    let expected = match name {
+        "alloc" | "dealloc" | "free" | "load" | "grow" | "read8" | "read16" | "read32" => 1,
+        "realloc" | "store" | "read" | "dump" | "write8" | "write16" | "write32" => 2,
+        "size" => 0,
+        _ => return Err(format!("unknown command '{}'", name)),
+    };
+    if args.len() != expected {
+        return Err(format!("'{}' takes {} argument(s), got {}", name, expected, args.len()));
+    }
+    Ok(match name {
+        "alloc" => Command::Alloc(parse_operand(args[0])?),
+        "dealloc" | "free" => Command::Dealloc(parse_operand(args[0])?),
+        "realloc" => Command::Realloc(parse_operand(args[0])?, parse_operand(args[1])?),
+        "store" => Command::Store(parse_operand(args[0])?, parse_operand(args[1])?),
+        "load" => Command::Load(parse_operand(args[0])?),
+        "grow" => Command::Grow(parse_operand(args[0])?),
+        "read" => Command::Read(parse_operand(args[0])?, parse_operand(args[1])?),
+        "dump" => Command::Dump(parse_operand(args[0])?, parse_operand(args[1])?),
+        "read8" | "read16" | "read32" => Command::ReadInt(name[4..].parse().unwrap(), parse_operand(args[0])?),
+        "write8" | "write16" | "write32" => {
+            Command::WriteInt(name[5..].parse().unwrap(), parse_operand(args[0])?, parse_operand(args[1])?)
+        }
+        _ => Command::Size,
+    })
+}
+
+// parse_statement parses a line of the form `[$name =] <command> <args...>`.
+pub fn parse_statement(line: &str) -> Result<Statement, String> {
+    let (bind, rest) = match line.split_once('=') {
+        Some((lhs, rhs)) => {
+            let lhs = lhs.trim();
+            let name = lhs
+                .strip_prefix('$')
+                .ok_or_else(|| format!("expected a handle name before '=', got '{}'", lhs))?;
+            (Some(parse_handle_name(name)?), rhs)
+        }
+        None => (None, line),
+    };
+    let tokens: Vec<&str> = rest.split_whitespace().collect();
+    let (name, args) = tokens.split_first().ok_or("expected a command")?;
+    let command = parse_command(name, args)?;
+    if bind.is_some() && !matches!(command, Command::Alloc(_) | Command::Realloc(..)) {
+        return Err(format!("'{}' doesn't return an address to bind", name));
+    }
+    Ok(Statement { bind, command })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle(name: &str, offset: i32) -> Operand {
+        Operand::Handle(name.to_string(), offset)
+    }
+
+    #[test]
+    fn numbers_cover_the_i32_and_u32_ranges() {
+        assert_eq!(parse_number("0"), Ok(0));
+        assert_eq!(parse_number("0x10"), Ok(16));
+        assert_eq!(parse_number("0X10"), Ok(16));
+        assert_eq!(parse_number("-0x10"), Ok(-16));
+        assert_eq!(parse_number("-2147483648"), Ok(i32::MIN));
+        assert_eq!(parse_number("2147483647"), Ok(i32::MAX));
+        assert_eq!(parse_number("4294967295"), Ok(-1));
+        assert_eq!(parse_number("0xFFFFFFFF"), Ok(-1));
+        assert_eq!(parse_number("-2147483649"), Err("number '-2147483649' doesn't fit in 32 bits".to_string()));
+        assert_eq!(parse_number("4294967296"), Err("number '4294967296' doesn't fit in 32 bits".to_string()));
+        assert_eq!(parse_number("0x"), Err("invalid number '0x'".to_string()));
+        assert_eq!(parse_number("12a"), Err("invalid number '12a'".to_string()));
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(parse_operand("-4"), Ok(Operand::Literal(-4)));
+        assert_eq!(parse_operand("$a"), Ok(handle("a", 0)));
+        assert_eq!(parse_operand("$a+0x10"), Ok(handle("a", 16)));
+        assert_eq!(parse_operand("$b_1-4"), Ok(handle("b_1", -4)));
+        assert_eq!(parse_operand("$"), Err("invalid handle name '$'".to_string()));
+        assert_eq!(parse_operand("$a+x"), Err("invalid number 'x'".to_string()));
+    }
+
+    #[test]
+    fn memory_commands() {
+        assert_eq!(parse_command("read", &["$a", "8"]), Ok(Command::Read(handle("a", 0), Operand::Literal(8))));
+        assert_eq!(
+            parse_command("write", &["$a+4", "1", "0xff"]),
+            Ok(Command::Write(handle("a", 4), vec![Operand::Literal(1), Operand::Literal(255)]))
+        );
+        assert_eq!(parse_command("read16", &["2"]), Ok(Command::ReadInt(16, Operand::Literal(2))));
+        assert_eq!(parse_command("write32", &["0", "-1"]), Ok(Command::WriteInt(32, Operand::Literal(0), Operand::Literal(-1))));
+        assert_eq!(parse_command("write", &["0"]), Err("'write' takes an address and at least one byte".to_string()));
+        assert_eq!(parse_command("read", &["0"]), Err("'read' takes 2 argument(s), got 1".to_string()));
+        assert_eq!(parse_command("read64", &["0"]), Err("unknown command 'read64'".to_string()));
+        assert_eq!(parse_command("dump", &["$a-4", "64"]), Ok(Command::Dump(handle("a", -4), Operand::Literal(64))));
+        assert_eq!(parse_command("dump", &["0"]), Err("'dump' takes 2 argument(s), got 1".to_string()));
+    }
+
+    #[test]
+    fn statements() {
+        let statement = parse_statement("$b = realloc $a 32").unwrap();
+        assert_eq!(statement.bind.as_deref(), Some("b"));
+        assert_eq!(statement.command, Command::Realloc(handle("a", 0), Operand::Literal(32)));
+        assert_eq!(parse_statement("free $a").unwrap().bind, None);
+        assert_eq!(parse_statement("$v = load $a"), Err("'load' doesn't return an address to bind".to_string()));
+        assert_eq!(parse_statement("a = alloc 8"), Err("expected a handle name before '=', got 'a'".to_string()));
+        assert_eq!(parse_statement("  "), Err("expected a command".to_string()));
+    }
+}
@@ -0,0 +1,145 @@
+use crate::baseline::{self, Timing};
+use crate::bench::format_duration;
+use crate::json;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// Comparing runs every workload against every module and reports the main
+// metrics of each combination side by side, as Markdown (ready to paste into
+// the README), CSV or JSON.
+
+// COLUMNS lists the reported metrics with their headings.
+const COLUMNS: [(&str, &str); 6] = [
+    ("fuel.call", "Fuel/op"),
+    ("time.p50", "p50"),
+    ("time.p99", "p99"),
+    ("pages", "Peak pages"),
+    ("utilization", "Utilization"),
+    ("fragmentation.mean", "Fragmentation"),
+];
+
+// Row holds the metrics of a workload run against a module, or the error
+// that stopped it.
+pub struct Row {
+    pub module: String,
+    pub workload: String,
+    pub values: Result<Vec<(String, f64)>, String>,
+}
+
+impl Row {
+    fn value(&self, metric: &str) -> Option<f64> {
+        let values = self.values.as_ref().ok()?;
+        values.iter().find(|(name, _)| name == metric).map(|(_, value)| *value)
+    }
+}
+
+// modules returns the allocator modules in the directory, i.e. the files
+// named like `1_minimal.wat`, in order.
+pub fn modules(dir: impl AsRef<Path>) -> Result<Vec<String>, Box<dyn Error>> {
+    let dir = dir.as_ref();
+    let mut modules: Vec<String> = fs::read_dir(dir)
+        .map_err(|err| format!("{}: {}", dir.display(), err))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+            name.contains('_') && name.ends_with(".wat")
+        })
+        .map(|path| path.display().to_string())
+        .collect();
+    modules.sort();
+    Ok(modules)
+}
+
+// run measures every workload against every module.
+pub fn run(modules: &[String], workloads: &[String], seed: u64, timing: Timing) -> Vec<Row> {
+    let mut rows = Vec::new();
+    for module in modules {
+        for workload in workloads {
+            let values = baseline::measure(module, workload, seed, Some(timing))
+                .map_err(|err| err.to_string().lines().next().unwrap_or("").to_string());
+            rows.push(Row { module: module.clone(), workload: workload.clone(), values });
+        }
+    }
+    rows
+}
+
+// format_cell formats a metric for the Markdown table.
+fn format_cell(metric: &str, value: f64) -> String {
+    match metric {
+        "time.p50" | "time.p99" => format_duration(value as u64),
+        "utilization" | "fragmentation.mean" => format!("{:.1}%", value * 100.0),
+        "fuel.call" => format!("{:.1}", value),
+        _ => format!("{}", value),
+    }
+}
+
+// markdown formats the rows as a Markdown table, linking each module the way
+// the README's implementation table does.
+pub fn markdown(rows: &[Row]) -> String {
+    let mut lines = vec![
+        ["Implementation", "Workload"].iter().chain(COLUMNS.iter().map(|(_, heading)| heading)).copied().collect::<Vec<_>>().join(" | "),
+        vec!["---"; COLUMNS.len() + 2].join(" | "),
+    ];
+    for row in rows {
+        let name = Path::new(&row.module).file_name().map_or(row.module.clone(), |name| name.to_string_lossy().to_string());
+        let mut cells = vec![format!("[{}]({})", name, row.module), row.workload.clone()];
+        match &row.values {
+            Ok(_) => {
+                for (metric, _) in COLUMNS {
+                    cells.push(row.value(metric).map_or("-".to_string(), |value| format_cell(metric, value)));
+                }
+            }
+            Err(err) => {
+                cells.push(format!("failed: {}", err.replace('|', "\\|")));
+                cells.extend(vec![String::new(); COLUMNS.len() - 1]);
+            }
+        }
+        lines.push(cells.join(" | "));
+    }
+    lines.iter().map(|line| format!("| {} |", line)).collect::<Vec<_>>().join("\n")
+}
+
+// csv_field quotes a CSV field if needed.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+// csv formats the rows as CSV, with raw values: fuel per call, nanoseconds,
+// pages and ratios.
+pub fn csv(rows: &[Row]) -> String {
+    let mut lines = vec![["module", "workload"].iter().chain(COLUMNS.iter().map(|(metric, _)| metric)).copied().collect::<Vec<_>>().join(",") + ",error"];
+    for row in rows {
+        let mut fields = vec![csv_field(&row.module), csv_field(&row.workload)];
+        for (metric, _) in COLUMNS {
+            fields.push(row.value(metric).map_or(String::new(), |value| value.to_string()));
+        }
+        fields.push(row.values.as_ref().err().map_or(String::new(), |err| csv_field(err)));
+        lines.push(fields.join(","));
+    }
+    lines.join("\n")
+}
+
+// to_json formats the rows as a JSON array, with every measured metric.
+pub fn to_json(rows: &[Row]) -> String {
+    let rows: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            let result = match &row.values {
+                Ok(values) => {
+                    let values: Vec<String> =
+                        values.iter().map(|(metric, value)| format!("{}: {}", json::string(metric), value)).collect();
+                    format!("\"metrics\": {{{}}}", values.join(", "))
+                }
+                Err(err) => format!("\"error\": {}", json::string(err)),
+            };
+            format!("{{\"module\": {}, \"workload\": {}, {}}}", json::string(&row.module), json::string(&row.workload), result)
+        })
+        .collect();
+    format!("[{}]", rows.join(", "))
+}
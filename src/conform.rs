@@ -0,0 +1,371 @@
+use crate::allocator::{self, WasmAllocator};
+use crate::ops::PAGE_SIZE;
+use std::error::Error;
+
+// The conformance suite checks that an allocator follows the alloc, dealloc
+// and realloc contract described in the README. Each check runs against a
+// fresh instance of the module and only uses the exported functions.
+
+// Check is a single named conformance check.
+pub struct Check {
+    pub name: &'static str,
+    pub description: &'static str,
+    run: fn(&mut WasmAllocator) -> Result<(), String>,
+}
+
+// CHECKS lists every conformance check in the order they are run.
+pub const CHECKS: &[Check] = &[
+    Check {
+        name: "zero-size",
+        description: "alloc(0) returns 0 without allocating anything",
+        run: check_zero_size,
+    },
+    Check {
+        name: "in-bounds",
+        description: "allocated blocks lie inside memory.size",
+        run: check_in_bounds,
+    },
+    Check {
+        name: "no-overlap",
+        description: "live blocks don't overlap and keep their contents",
+        run: check_no_overlap,
+    },
+    Check {
+        name: "realloc",
+        description: "realloc preserves contents up to min(old size, new size)",
+        run: check_realloc,
+    },
+    Check {
+        name: "reuse",
+        description: "deallocated blocks are reused instead of growing the memory",
+        run: check_reuse,
+    },
+    Check {
+        name: "huge",
+        description: "requests that can't be satisfied don't return unusable blocks",
+        run: check_huge,
+    },
+];
+
+// SIZES is a mix of request sizes, small and large, aligned and unaligned.
+const SIZES: &[i32] = &[1, 3, 4, 8, 12, 16, 24, 100, 1000, 4096, 65536, 100000];
+
+// trapped returns the first line of an error, which names the call that
+// trapped.
+fn trapped(err: allocator::Error) -> String {
+    err.to_string().lines().next().unwrap_or("").to_string()
+}
+
+// memory_bytes returns the current size of the memory in bytes.
+fn memory_bytes(allocator: &mut WasmAllocator) -> Result<u64, String> {
+    let pages = allocator.memory_pages().map_err(trapped)?;
+    Ok(pages as u64 * PAGE_SIZE)
+}
+
+fn alloc(allocator: &mut WasmAllocator, size: i32) -> Result<i32, String> {
+    allocator.alloc(size).map_err(trapped)
+}
+
+fn dealloc(allocator: &mut WasmAllocator, address: i32) -> Result<(), String> {
+    allocator.dealloc(address).map_err(trapped)
+}
+
+fn realloc(allocator: &mut WasmAllocator, address: i32, size: i32) -> Result<i32, String> {
+    allocator.realloc(address, size).map_err(trapped)
+}
+
+// check_bounds checks that the block lies inside the memory.
+fn check_bounds(allocator: &mut WasmAllocator, address: i32, size: i32) -> Result<(), String> {
+    let end = address as u32 as u64 + size as u32 as u64;
+    let limit = memory_bytes(allocator)?;
+    if end > limit {
+        return Err(format!(
+            "block [{}, {}) of size {} ends beyond the memory size of {} bytes",
+            address as u32, end, size as u32, limit
+        ));
+    }
+    Ok(())
+}
+
+// pattern returns the 32-bit word we write at `offset` in a block tagged with
+// `tag`, so that every block (and every word in it) has distinct contents.
+fn pattern(tag: i32, offset: i32) -> i32 {
+    tag.wrapping_mul(0x01000193) ^ offset
+}
+
+// tail returns the bytes of the pattern that follow the last whole word of a
+// block of `size` bytes.
+fn tail(size: i32, tag: i32) -> Vec<u8> {
+    let end = size / 4 * 4;
+    pattern(tag, end).to_le_bytes()[..(size - end) as usize].to_vec()
+}
+
+// fill writes the block's pattern into every whole 32-bit word of the block,
+// and into the bytes after them if the module exports its memory.
+fn fill(allocator: &mut WasmAllocator, address: i32, size: i32, tag: i32) -> Result<(), String> {
+    for offset in (0..size / 4 * 4).step_by(4) {
+        let target = address.wrapping_add(offset);
+        allocator.store(target, pattern(tag, offset)).map_err(trapped)?;
+    }
+    if allocator.memory().is_some() {
+        allocator.write_bytes(address.wrapping_add(size / 4 * 4) as u32, &tail(size, tag)).map_err(trapped)?;
+    }
+    Ok(())
+}
+
+// verify checks the pattern written by fill, up to `size` bytes.
+fn verify(allocator: &mut WasmAllocator, address: i32, size: i32, tag: i32) -> Result<(), String> {
+    for offset in (0..size / 4 * 4).step_by(4) {
+        let target = address.wrapping_add(offset);
+        let value = allocator.load(target).map_err(trapped)?;
+        if value != pattern(tag, offset) {
+            return Err(format!(
+                "block at {} was modified at offset {}: expected {:#x}, found {:#x}",
+                address,
+                offset,
+                pattern(tag, offset),
+                value
+            ));
+        }
+    }
+    if allocator.memory().is_some() {
+        let end = size / 4 * 4;
+        let bytes = allocator.read_bytes(address.wrapping_add(end) as u32, (size - end) as usize).map_err(trapped)?;
+        if bytes != tail(size, tag) {
+            return Err(format!("block at {} was modified in its last {} bytes: expected {:02x?}, found {:02x?}", address, size - end, tail(size, tag), bytes));
+        }
+    }
+    Ok(())
+}
+
+// check_disjoint checks that no two of the (address, size) blocks overlap.
+fn check_disjoint(blocks: &[(i32, i32)]) -> Result<(), String> {
+    let mut sorted: Vec<(u64, u64)> = blocks
+        .iter()
+        .map(|&(address, size)| (address as u32 as u64, size as u32 as u64))
+        .collect();
+    sorted.sort();
+    for pair in sorted.windows(2) {
+        let ((a, a_size), (b, b_size)) = (pair[0], pair[1]);
+        if a + a_size > b {
+            return Err(format!(
+                "block [{}, {}) overlaps block [{}, {})",
+                a,
+                a + a_size,
+                b,
+                b + b_size
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_zero_size(allocator: &mut WasmAllocator) -> Result<(), String> {
+    let before = memory_bytes(allocator)?;
+    for _ in 0..3 {
+        let address = alloc(allocator, 0)?;
+        if address != 0 {
+            return Err(format!("alloc(0) returned {}", address));
+        }
+    }
+    let after = memory_bytes(allocator)?;
+    if after != before {
+        return Err(format!("alloc(0) grew the memory from {} to {} bytes", before, after));
+    }
+    Ok(())
+}
+
+fn check_in_bounds(allocator: &mut WasmAllocator) -> Result<(), String> {
+    let mut blocks = Vec::new();
+    for &size in SIZES {
+        let address = alloc(allocator, size)?;
+        check_bounds(allocator, address, size)?;
+        blocks.push((address, size));
+    }
+    // The blocks must remain in bounds after they're all allocated as well.
+    for &(address, size) in &blocks {
+        check_bounds(allocator, address, size)?;
+    }
+    Ok(())
+}
+
+fn check_no_overlap(allocator: &mut WasmAllocator) -> Result<(), String> {
+    let mut live: Vec<(i32, i32, i32)> = Vec::new();
+    let mut tag = 0;
+
+    // Allocate every size twice, freeing every other block after the first
+    // round so that the second round can reuse the holes.
+    for round in 0..2 {
+        for &size in SIZES {
+            tag += 1;
+            let address = alloc(allocator, size)?;
+            fill(allocator, address, size, tag)?;
+            live.push((address, size, tag));
+        }
+        if round == 0 {
+            let (kept, freed): (Vec<_>, Vec<_>) =
+                live.iter().enumerate().partition(|(i, _)| i % 2 == 0);
+            for (_, &(address, _, _)) in freed {
+                dealloc(allocator, address)?;
+            }
+            live = kept.into_iter().map(|(_, &block)| block).collect();
+        }
+        let blocks: Vec<(i32, i32)> = live.iter().map(|&(a, s, _)| (a, s)).collect();
+        check_disjoint(&blocks)?;
+    }
+    for &(address, size, tag) in &live {
+        verify(allocator, address, size, tag)?;
+    }
+    Ok(())
+}
+
+fn check_realloc(allocator: &mut WasmAllocator) -> Result<(), String> {
+    // Each pair is an original size and the size to realloc to.
+    let cases = [(16, 64), (64, 16), (100, 100), (4, 4096), (4096, 8), (24, 100000)];
+    let mut tag = 0;
+    for (old, new) in cases {
+        tag += 1;
+        let address = alloc(allocator, old)?;
+        fill(allocator, address, old, tag)?;
+        // Keep a neighbor alive so that growing the block in place without
+        // accounting for it would clobber the neighbor.
+        let neighbor = alloc(allocator, 16)?;
+        fill(allocator, neighbor, 16, -tag)?;
+        let moved = realloc(allocator, address, new)?;
+        check_bounds(allocator, moved, new)?;
+        verify(allocator, moved, old.min(new), tag)
+            .map_err(|err| format!("after realloc({}, {}): {}", old, new, err))?;
+        verify(allocator, neighbor, 16, -tag)
+            .map_err(|err| format!("after realloc({}, {}): {}", old, new, err))?;
+        check_disjoint(&[(moved, new), (neighbor, 16)])?;
+        dealloc(allocator, moved)?;
+        dealloc(allocator, neighbor)?;
+    }
+    Ok(())
+}
+
+fn check_reuse(allocator: &mut WasmAllocator) -> Result<(), String> {
+    // Warm up with one cycle so any initial growth is accounted for.
+    let address = alloc(allocator, 4096)?;
+    dealloc(allocator, address)?;
+    let before = memory_bytes(allocator)?;
+    // This would allocate 1MiB in total if nothing was reused.
+    for _ in 0..256 {
+        let address = alloc(allocator, 4096)?;
+        dealloc(allocator, address)?;
+    }
+    let after = memory_bytes(allocator)?;
+    if after > before {
+        return Err(format!(
+            "memory grew from {} to {} bytes while repeatedly allocating and deallocating 4096 bytes",
+            before, after
+        ));
+    }
+    Ok(())
+}
+
+fn check_huge(allocator: &mut WasmAllocator) -> Result<(), String> {
+    // A small live block makes the largest sizes below impossible to satisfy.
+    let small = alloc(allocator, 16)?;
+    fill(allocator, small, 16, 1)?;
+    for size in [0x7FFF_FFF0u32 as i32, 0xFFFF_FFF0u32 as i32, -1] {
+        // Both trapping and returning zero are acceptable ways of failing, but
+        // a non-zero address must be a usable block.
+        match allocator.alloc(size) {
+            Err(_) | Ok(0) => {}
+            Ok(address) => {
+                check_bounds(allocator, address, size)?;
+                check_disjoint(&[(small, 16), (address, size)])?;
+            }
+        }
+    }
+    verify(allocator, small, 16, 1)
+}
+
+// Report is the outcome of running the conformance suite.
+pub struct Report {
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+// run runs the selected checks against fresh instances of the module and
+// prints a line per check. A check is selected if it's in `only` (or `only` is
+// empty) and it's not in `skip`.
+pub fn run(module: &str, only: &[String], skip: &[String]) -> Result<Report, Box<dyn Error>> {
+    for name in only.iter().chain(skip) {
+        if !CHECKS.iter().any(|check| check.name == name) {
+            return Err(format!("unknown check '{}'", name).into());
+        }
+    }
+    let mut report = Report { passed: 0, failed: 0, skipped: 0 };
+    for check in CHECKS {
+        let selected = only.is_empty() || only.iter().any(|name| name == check.name);
+        if !selected || skip.iter().any(|name| name == check.name) {
+            println!("SKIP {:12} {}", check.name, check.description);
+            report.skipped += 1;
+            continue;
+        }
+        match (check.run)(&mut WasmAllocator::new(module)?) {
+            Ok(()) => {
+                println!("PASS {:12} {}", check.name, check.description);
+                report.passed += 1;
+            }
+            Err(err) => {
+                println!("FAIL {:12} {}", check.name, check.description);
+                println!("     {:12} {}", "", err);
+                report.failed += 1;
+            }
+        }
+    }
+    println!(
+        "{} passed, {} failed, {} skipped",
+        report.passed, report.failed, report.skipped
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmtime::Module;
+
+    // allocator instantiates a module given as text.
+    fn allocator(wat: &str) -> WasmAllocator {
+        let engine = WasmAllocator::engine().unwrap();
+        WasmAllocator::instantiate(&engine, &Module::new(&engine, wat).unwrap()).unwrap()
+    }
+
+    // run runs the check named `name` against a fresh instance of the module.
+    fn run(wat: &str, name: &str) -> Result<(), String> {
+        let check = CHECKS.iter().find(|check| check.name == name).unwrap();
+        (check.run)(&mut allocator(wat))
+    }
+
+    #[test]
+    fn disjoint_blocks() {
+        assert!(check_disjoint(&[(16, 8), (0, 16), (24, 0), (24, 4)]).is_ok());
+        assert_eq!(check_disjoint(&[(16, 8), (8, 12)]), Err("block [8, 20) overlaps block [16, 24)".to_string()));
+        // Addresses are unsigned, so high addresses sort last.
+        assert!(check_disjoint(&[(-16, 8), (16, 8)]).is_ok());
+    }
+
+    #[test]
+    fn linked_allocator_conforms() {
+        for check in CHECKS {
+            assert_eq!(run(include_str!("2_linked.wat"), check.name), Ok(()), "{}", check.name);
+        }
+    }
+
+    #[test]
+    fn overlapping_blocks_fail() {
+        // Every non-empty block is at the same address.
+        let same = r#"(module
+            (memory (export "memory") 2)
+            (func (export "alloc") (param i32) (result i32) (select (i32.const 0) (i32.const 8) (i32.eqz (local.get 0))))
+            (func (export "dealloc") (param i32))
+            (func (export "realloc") (param i32 i32) (result i32) (local.get 0)))"#;
+        assert!(run(same, "zero-size").is_ok());
+        assert!(run(same, "no-overlap").unwrap_err().contains("overlaps"));
+    }
+}
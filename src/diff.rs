@@ -0,0 +1,121 @@
+use crate::allocator::{self, WasmAllocator};
+use crate::ops::{self, Effect, Op, Runner};
+use crate::rng::Rng;
+use std::error::Error;
+use std::mem;
+
+// Differential testing applies the same random operations to two allocator
+// modules, each in its own store, and compares what can be observed. The
+// modules return different addresses, so those aren't compared, but both
+// modules must agree on whether an operation trapped, with which trap code,
+// failed in another way or ran out of memory.
+// Loads only read back words that were stored, so their values must match.
+
+// CONTEXT is the number of operations printed before a difference.
+const CONTEXT: usize = 5;
+
+// Difference is the first operation whose outcome differs between modules.
+pub struct Difference {
+    pub index: usize,
+    pub message: String,
+}
+
+fn describe(result: &Result<Effect, allocator::Error>) -> String {
+    match result {
+        Ok(Effect::Address(address)) => format!("returned address {}", address),
+        Ok(Effect::Value(value)) => format!("returned {}", value),
+        Ok(Effect::Done) => "returned".to_string(),
+        Ok(Effect::OutOfMemory) => "ran out of memory".to_string(),
+        Ok(Effect::Skipped) => "skipped".to_string(),
+        Err(trap) => trap.to_string().lines().next().unwrap_or("").to_string(),
+    }
+}
+
+// equivalent returns whether two outcomes of the same operation match. Errors
+// match if they're of the same kind, and traps if they have the same code.
+fn equivalent(a: &Result<Effect, allocator::Error>, b: &Result<Effect, allocator::Error>) -> bool {
+    match (a, b) {
+        (Err(allocator::Error::Trap(a)), Err(allocator::Error::Trap(b))) => a.code == b.code,
+        (Err(a), Err(b)) => mem::discriminant(a) == mem::discriminant(b),
+        (Ok(Effect::Address(_)), Ok(Effect::Address(_))) => true,
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// compare applies the operations to both modules and returns the first
+// difference, if any.
+pub fn compare(a: &str, b: &str, ops: &[Op]) -> Result<Option<Difference>, Box<dyn Error>> {
+    let mut runner_a = Runner::new(WasmAllocator::new(a)?);
+    let mut runner_b = Runner::new(WasmAllocator::new(b)?);
+    for (index, op) in ops.iter().enumerate() {
+        let result_a = runner_a.apply(op);
+        let result_b = runner_b.apply(op);
+        if !equivalent(&result_a, &result_b) {
+            let message = format!(
+                "{}: {}\n{}: {}",
+                a,
+                describe(&result_a),
+                b,
+                describe(&result_b)
+            );
+            return Ok(Some(Difference { index, message }));
+        }
+    }
+    Ok(None)
+}
+
+// run compares the modules over `runs` random sequences of `count` operations,
+// seeded with `seed`, `seed + 1`, etc., up to u64::MAX. It prints the first difference found
+// along with the operations leading to it, and returns whether none was found.
+pub fn run(a: &str, b: &str, seed: u64, runs: u64, count: usize) -> Result<bool, Box<dyn Error>> {
+    let seeds = seed..seed.saturating_add(runs);
+    let runs = seeds.end - seeds.start;
+    for seed in seeds {
+        let ops = ops::random(&mut Rng::new(seed), count);
+        if let Some(difference) = compare(a, b, &ops)? {
+            println!("Difference with seed {} at operation {}:", seed, difference.index);
+            let first = difference.index.saturating_sub(CONTEXT);
+            for (i, op) in ops.iter().enumerate().take(difference.index + 1).skip(first) {
+                println!("  {:6}  {}", i, op);
+            }
+            println!("{}", difference.message);
+            return Ok(false);
+        }
+    }
+    println!("No differences in {} run(s) of {} operations", runs, count);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::TrapInfo;
+    use wasmtime::TrapCode;
+
+    fn trap(code: Option<TrapCode>) -> Result<Effect, allocator::Error> {
+        Err(allocator::Error::Trap(TrapInfo { operation: "alloc(8)".to_string(), code, message: String::new(), backtrace: Vec::new() }))
+    }
+
+    #[test]
+    fn equivalent_outcomes() {
+        assert!(equivalent(&Ok(Effect::Address(8)), &Ok(Effect::Address(1024))));
+        assert!(equivalent(&Ok(Effect::Value(7)), &Ok(Effect::Value(7))));
+        assert!(!equivalent(&Ok(Effect::Value(7)), &Ok(Effect::Value(8))));
+        assert!(!equivalent(&Ok(Effect::Address(8)), &Ok(Effect::OutOfMemory)));
+        assert!(!equivalent(&Ok(Effect::Done), &trap(None)));
+    }
+
+    #[test]
+    fn equivalent_errors() {
+        let oob = || trap(Some(TrapCode::MemoryOutOfBounds));
+        assert!(equivalent(&oob(), &oob()));
+        assert!(equivalent(&trap(None), &trap(None)));
+        assert!(!equivalent(&oob(), &trap(Some(TrapCode::UnreachableCodeReached))));
+        assert!(!equivalent(&oob(), &trap(None)));
+        let host = || Err(allocator::Error::NoMemory);
+        assert!(equivalent(&host(), &host()));
+        assert!(!equivalent(&oob(), &host()));
+        assert!(!equivalent(&host(), &Err(allocator::Error::Walk("stuck".to_string()))));
+    }
+}
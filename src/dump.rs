@@ -0,0 +1,199 @@
+use crate::allocator::{self, WasmAllocator};
+use crate::ops::{Op, Runner};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+// Hex dumps of the bytes of a module's memory, optionally annotated with what
+// the host knows about them.
+
+// ROW is the number of bytes per row of a dump.
+pub const ROW: u32 = 16;
+
+// row formats the bytes of the row at address `row` that lie in the range of
+// `bytes` starting at `start`, as hex and as ASCII. Bytes outside the range
+// are left blank.
+fn row(row: u64, start: u64, bytes: &[u8]) -> (String, String) {
+    let end = start + bytes.len() as u64;
+    let mut hex = format!("{:#010x} ", row);
+    let mut ascii = String::new();
+    for i in 0..ROW as u64 {
+        let at = row + i;
+        if i == ROW as u64 / 2 {
+            hex.push(' ');
+        }
+        if at < start || at >= end {
+            hex.push_str("   ");
+            ascii.push(' ');
+            continue;
+        }
+        let byte = bytes[(at - start) as usize];
+        hex.push_str(&format!(" {:02x}", byte));
+        ascii.push(if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' });
+    }
+    (hex, ascii)
+}
+
+// rows returns the addresses of the rows covering `len` bytes at `start`.
+// Rows are aligned to ROW bytes.
+fn rows(start: u64, len: usize) -> impl Iterator<Item = u64> {
+    let end = start + len as u64;
+    (start - start % ROW as u64..end).step_by(ROW as usize)
+}
+
+// hex formats bytes read at `address` as rows of ROW hex bytes, each starting
+// with its address. Rows are aligned to ROW bytes, and the bytes of the first
+// and last rows outside the range are left blank:
+//
+//     0x00000010              68 65 6c 6c  6f 00 00 00 2a 00 00 00
+//     0x00000020  ff ff ff ff
+pub fn hex(address: u32, bytes: &[u8]) -> String {
+    let start = address as u64;
+    let lines: Vec<String> = rows(start, bytes.len()).map(|at| row(at, start, bytes).0.trim_end().to_string()).collect();
+    lines.join("\n")
+}
+
+// Mark is a region of the memory known to the host, such as a block or a
+// header. Marks can be empty, e.g. for a handle to an unknown block.
+#[derive(Debug, Clone)]
+pub struct Mark {
+    pub start: u64,
+    pub end: u64,
+    pub label: String,
+}
+
+// marks returns what the host knows about the memory of the module: the
+// blocks, headers and free list pointers of the heap walk if the module
+// exports it, and otherwise the live blocks given as (address, size). Blocks
+// are labeled with the names given for their address, e.g. handles.
+pub fn marks(allocator: &mut WasmAllocator, live: &[(u64, u64)], names: &HashMap<u64, Vec<String>>) -> Result<Vec<Mark>, allocator::Error> {
+    let named = |label: String, address: u64| match names.get(&address) {
+        Some(names) => format!("{} ({})", label, names.join(", ")),
+        None => label,
+    };
+    let mut marks = Vec::new();
+    let mut blocks = HashSet::new();
+    match allocator.walk()? {
+        Some(walk) => {
+            let free_list = allocator.free_list()?;
+            let next: HashMap<u64, u64> = free_list.iter().flat_map(|list| list.windows(2)).map(|pair| (pair[0], pair[1])).collect();
+            let mut cursor = 0;
+            for block in &walk {
+                if cursor < block.address {
+                    marks.push(Mark { start: cursor, end: block.address, label: format!("header of {:#x}", block.address) });
+                }
+                let mut label = format!("{} block {:#x}, {} bytes", if block.free { "free" } else { "used" }, block.address, block.size);
+                match (&free_list, next.get(&block.address)) {
+                    (_, Some(next)) => label.push_str(&format!(", next free {:#x}", next)),
+                    (Some(list), None) if list.last() == Some(&block.address) => label.push_str(", last free"),
+                    _ => {}
+                }
+                marks.push(Mark { start: block.address, end: block.address + block.size, label: named(label, block.address) });
+                blocks.insert(block.address);
+                cursor = cursor.max(block.address + block.size);
+            }
+        }
+        None => {
+            for &(address, size) in live {
+                let label = format!("live block {:#x}, {} bytes", address, size);
+                marks.push(Mark { start: address, end: address + size, label: named(label, address) });
+                blocks.insert(address);
+            }
+        }
+    }
+    // Names of addresses that aren't blocks are shown on their own.
+    for (&address, names) in names {
+        if !blocks.contains(&address) {
+            marks.push(Mark { start: address, end: address, label: names.join(", ") });
+        }
+    }
+    marks.sort_by_key(|mark| (mark.start, mark.end));
+    Ok(marks)
+}
+
+// annotated formats bytes read at `address` like hex, followed on each row by
+// the bytes as ASCII and the marks starting in the row. A mark that starts
+// before the first row is shown on it.
+//
+//     0x00000080  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|  live block 0x86, 14 bytes (#4)
+//     0x00000090  68 69 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |hi..............|  live block 0x94, 14 bytes (#5)
+pub fn annotated(address: u32, bytes: &[u8], marks: &[Mark]) -> String {
+    let start = address as u64;
+    let end = start + bytes.len() as u64;
+    let mut lines = Vec::new();
+    for at in rows(start, bytes.len()) {
+        let (hex, ascii) = row(at, start, bytes);
+        let notes: Vec<&str> = marks
+            .iter()
+            .filter(|mark| {
+                let anchor = mark.start.max(start);
+                anchor >= at && anchor < at + ROW as u64 && anchor < end && (mark.end > start || mark.start >= start)
+            })
+            .map(|mark| mark.label.as_str())
+            .collect();
+        lines.push(format!("{}  |{}|  {}", hex, ascii, notes.join("; ")).trim_end().to_string());
+    }
+    lines.join("\n")
+}
+
+// replay runs the operations up to the one at index `at` against the module
+// at `path`, and returns an annotated dump of `len` bytes at `address`. Live
+// blocks are named after the ids of the operations, e.g. "#3".
+pub fn replay(path: &str, ops: &[Op], at: usize, address: u32, len: usize) -> Result<String, Box<dyn Error>> {
+    let mut runner = Runner::new(WasmAllocator::new(path)?);
+    for (index, op) in ops.iter().enumerate().take(at + 1) {
+        runner.step(index, op)?;
+    }
+    let mut names: HashMap<u64, Vec<String>> = HashMap::new();
+    for (id, address, _) in runner.live_ids() {
+        names.entry(address).or_default().push(format!("#{}", id));
+    }
+    let live = runner.live_sizes();
+    let bytes = runner.allocator.read_bytes(address, len)?;
+    let marks = marks(&mut runner.allocator, &live, &names)?;
+    Ok(annotated(address, &bytes, &marks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_rows_are_aligned() {
+        let bytes: Vec<u8> = (1..=16).collect();
+        let dump = hex(0x1c, &bytes);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1], "0x00000020  05 06 07 08 09 0a 0b 0c  0d 0e 0f 10");
+        // The bytes of the partial first row line up with the same bytes of a
+        // full row.
+        let full = hex(0x10, &(0xa0..=0xaf).collect::<Vec<u8>>());
+        assert_eq!(full, "0x00000010  a0 a1 a2 a3 a4 a5 a6 a7  a8 a9 aa ab ac ad ae af");
+        assert_eq!(lines[0].find("01 02 03 04"), full.find("ac ad ae af"));
+        assert!(lines[0].ends_with("01 02 03 04"));
+    }
+
+    #[test]
+    fn hex_of_nothing_is_empty() {
+        assert_eq!(hex(0x10, &[]), "");
+    }
+
+    #[test]
+    fn annotated_rows_are_aligned() {
+        let mark = |start, end, label: &str| Mark { start, end, label: label.to_string() };
+        let marks = [mark(0x18, 0x24, "block a"), mark(0x24, 0x2c, "block b"), mark(0x40, 0x48, "past the end")];
+        let dump = annotated(0x1c, b"abcd\x00efghijklmno", &marks);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "0x00000010                                       61 62 63 64  |            abcd|  block a",
+                "0x00000020  00 65 66 67 68 69 6a 6b  6c 6d 6e 6f              |.efghijklmno    |  block b",
+            ]
+        );
+        assert_eq!(lines[0].find('|'), lines[1].find('|'));
+        // The hex columns are those of a plain hex dump.
+        for (line, hex) in lines.iter().zip(hex(0x1c, b"abcd\x00efghijklmno").lines()) {
+            assert!(line.starts_with(hex));
+        }
+    }
+}
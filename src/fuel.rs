@@ -0,0 +1,166 @@
+use crate::allocator::WasmAllocator;
+use crate::bench::{self, Call, Histogram, OPERATIONS};
+use crate::json;
+use crate::ops::Op;
+use std::error::Error;
+use std::fmt;
+
+// Fuel accounting replays a workload once and reports the fuel consumed by
+// every alloc, dealloc and realloc call. Fuel counts the WebAssembly
+// instructions executed, so unlike timings it's the same on every machine and
+// every run, which makes allocator costs comparable exactly, e.g. in CI.
+
+// Worst is one of the most expensive calls of a workload.
+#[derive(Debug, Clone, Copy)]
+pub struct Worst {
+    pub call: Call,
+    pub op: Op,
+}
+
+impl fmt::Display for Worst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "op {} ({}) consumed {} fuel", self.call.index, self.op, self.call.fuel)
+    }
+}
+
+// Report holds the fuel consumed by the calls of a workload.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub module: String,
+    pub workload: String,
+    // histograms holds the fuel consumed by each call, as in OPERATIONS.
+    pub histograms: [Histogram; 3],
+    // maxima holds the most expensive call of each kind, as in OPERATIONS.
+    pub maxima: [Option<Call>; 3],
+    // worst holds the most expensive calls, most expensive first.
+    pub worst: Vec<Worst>,
+}
+
+impl Report {
+    // total returns the fuel consumed by all calls together.
+    pub fn total(&self) -> Histogram {
+        let mut total = Histogram::new();
+        for histogram in &self.histograms {
+            total.merge(histogram);
+        }
+        total
+    }
+
+    // rows returns the name, fuel and most expensive call of each kind of call
+    // that was made, followed by all calls together.
+    fn rows(&self) -> Vec<(&str, Histogram, Option<Call>)> {
+        let mut rows: Vec<(&str, Histogram, Option<Call>)> = (0..OPERATIONS.len())
+            .filter(|&kind| self.histograms[kind].count > 0)
+            .map(|kind| (OPERATIONS[kind], self.histograms[kind].clone(), self.maxima[kind]))
+            .collect();
+        let total = self.maxima.iter().flatten().max_by_key(|call| (call.fuel, std::cmp::Reverse(call.index)));
+        rows.push(("total", self.total(), total.copied()));
+        rows
+    }
+
+    // to_json encodes the report as a JSON object.
+    pub fn to_json(&self) -> String {
+        let operations: Vec<String> = self
+            .rows()
+            .iter()
+            .map(|(name, histogram, max)| {
+                format!(
+                    "{}: {{\"count\": {}, \"fuel\": {}, \"mean\": {:.1}, \"p50\": {}, \"p99\": {}, \"max\": {}, \"max_index\": {}}}",
+                    json::string(name),
+                    histogram.count,
+                    histogram.sum,
+                    mean(histogram),
+                    histogram.percentile(50.0),
+                    histogram.percentile(99.0),
+                    histogram.max,
+                    max.map_or("null".to_string(), |call| call.index.to_string())
+                )
+            })
+            .collect();
+        let worst: Vec<String> = self
+            .worst
+            .iter()
+            .map(|worst| {
+                format!(
+                    "{{\"index\": {}, \"op\": {}, \"fuel\": {}}}",
+                    worst.call.index,
+                    json::string(&worst.op.to_string()),
+                    worst.call.fuel
+                )
+            })
+            .collect();
+        format!(
+            "{{\"module\": {}, \"workload\": {}, \"operations\": {{{}}}, \"worst\": [{}]}}",
+            json::string(&self.module),
+            json::string(&self.workload),
+            operations.join(", "),
+            worst.join(", ")
+        )
+    }
+}
+
+fn mean(histogram: &Histogram) -> f64 {
+    if histogram.count == 0 {
+        return 0.0;
+    }
+    histogram.sum as f64 / histogram.count as f64
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "module:   {}", self.module)?;
+        writeln!(f, "workload: {}", self.workload)?;
+        writeln!(f)?;
+        write!(f, "{:8} {:>9} {:>12} {:>9} {:>8} {:>8} {:>8}  max at", "op", "count", "fuel", "mean", "p50", "p99", "max")?;
+        for (name, histogram, max) in self.rows() {
+            write!(
+                f,
+                "\n{:8} {:>9} {:>12} {:>9.1} {:>8} {:>8} {:>8}  {}",
+                name,
+                histogram.count,
+                histogram.sum,
+                mean(&histogram),
+                histogram.percentile(50.0),
+                histogram.percentile(99.0),
+                histogram.max,
+                max.map_or(String::new(), |call| format!("op {}", call.index))
+            )?;
+        }
+        if !self.worst.is_empty() {
+            write!(f, "\n\nmost expensive calls:")?;
+            for worst in &self.worst {
+                write!(f, "\n  {}", worst)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// run replays the operations against the module at `path` and reports the
+// fuel of each call, along with the `worst` most expensive calls. Of calls
+// with the same fuel, the earliest comes first.
+pub fn run(path: &str, workload: &str, ops: &[Op], worst: usize) -> Result<Report, Box<dyn Error>> {
+    let allocator = WasmAllocator::new(path)?;
+    let mut histograms: [Histogram; 3] = Default::default();
+    let mut maxima: [Option<Call>; 3] = [None; 3];
+    let mut calls: Vec<Call> = Vec::new();
+    bench::run_once(allocator, ops, |call| {
+        histograms[call.kind].record(call.fuel);
+        let max = &mut maxima[call.kind];
+        if max.is_none_or(|max| call.fuel > max.fuel) {
+            *max = Some(call);
+        }
+        if worst > 0 {
+            calls.push(call);
+        }
+    })?;
+    calls.sort_by_key(|call| (std::cmp::Reverse(call.fuel), call.index));
+    calls.truncate(worst);
+    Ok(Report {
+        module: path.to_string(),
+        workload: workload.to_string(),
+        histograms,
+        maxima,
+        worst: calls.into_iter().map(|call| Worst { call, op: ops[call.index] }).collect(),
+    })
+}
@@ -0,0 +1,231 @@
+use crate::allocator::WasmAllocator;
+use crate::ops::{Effect, Generator, Op, Runner};
+use crate::rng::{Rng, Source};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use wasmtime::Module;
+
+// Fuzzing decodes arbitrary bytes into a sequence of operations, runs it
+// against a fresh instance of an allocator, and checks a host-side shadow
+// model of every live block after each step: blocks must lie inside the
+// memory, live blocks must not overlap, and stored words must keep their
+// values. Traps are bugs unless they match one of the allowed messages.
+//
+// The same decoding and checks back the `fuzz` subcommand, which feeds them
+// random inputs or replays saved ones, and the libFuzzer target in fuzz/,
+// which feeds them coverage-guided inputs.
+
+// MAX_OPS bounds the number of operations decoded from a single input.
+const MAX_OPS: usize = 1000;
+
+// Input is a source of choices that reads fuzzer input bytes. Once the bytes
+// run out it only returns zeros.
+pub struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    pub fn new(data: &'a [u8]) -> Input<'a> {
+        Input { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Source for Input<'_> {
+    // next_u64 consumes four bytes at a time so that small mutations of the
+    // input only change a few choices.
+    fn next_u64(&mut self) -> u64 {
+        let n = self.data.len().min(4);
+        let mut bytes = [0; 8];
+        bytes[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        u64::from_le_bytes(bytes)
+    }
+}
+
+// decode turns fuzzer input into a valid sequence of operations.
+pub fn decode(data: &[u8]) -> Vec<Op> {
+    let mut input = Input::new(data);
+    let mut generator = Generator::default();
+    let mut ops = Vec::new();
+    while !input.is_empty() && ops.len() < MAX_OPS {
+        ops.extend(generator.next(&mut input));
+    }
+    ops
+}
+
+// Bug is an invariant violation, or an unexpected trap, at an operation.
+#[derive(Debug)]
+pub struct Bug {
+    pub index: usize,
+    pub message: String,
+}
+
+// ShadowBlock is what the host knows about a live block.
+struct ShadowBlock {
+    address: i32,
+    size: i32,
+    // words maps the offsets of the stored words to their values.
+    words: HashMap<i32, i32>,
+}
+
+fn span(address: i32, size: i32) -> (u64, u64) {
+    let start = address as u32 as u64;
+    (start, start + size as u32 as u64)
+}
+
+// check_placement checks that a block returned by alloc or realloc doesn't
+// overlap any other live block.
+fn check_placement(shadow: &HashMap<usize, ShadowBlock>, id: usize, address: i32, size: i32) -> Result<(), String> {
+    let (start, end) = span(address, size);
+    for (&other_id, other) in shadow {
+        let (other_start, other_end) = span(other.address, other.size);
+        if other_id != id && start < other_end && other_start < end {
+            return Err(format!(
+                "block #{} [{}, {}) overlaps live block #{} [{}, {})",
+                id, start, end, other_id, other_start, other_end
+            ));
+        }
+    }
+    Ok(())
+}
+
+// verify checks that every stored word of every live block still holds its
+// value.
+fn verify(allocator: &mut WasmAllocator, shadow: &HashMap<usize, ShadowBlock>) -> Result<(), String> {
+    for (id, block) in shadow {
+        for (&offset, &expected) in &block.words {
+            let address = block.address.wrapping_add(offset);
+            let value = allocator
+                .load(address)
+                .map_err(|trap| first_line(&trap.to_string()).to_string())?;
+            if value != expected {
+                return Err(format!(
+                    "block #{} at {} was modified at offset {}: expected {:#x}, found {:#x}",
+                    id, block.address, offset, expected, value
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn first_line(text: &str) -> &str {
+    text.lines().next().unwrap_or("")
+}
+
+// step applies an operation and updates the shadow model.
+fn step(runner: &mut Runner, shadow: &mut HashMap<usize, ShadowBlock>, op: &Op) -> Result<(), String> {
+    let effect = runner.apply(op).map_err(|trap| format!("trap: {}", trap.to_string().trim_end()))?;
+    match (*op, effect) {
+        (_, Effect::Skipped) => {}
+        (Op::Alloc { size, .. } | Op::Realloc { size, .. }, Effect::OutOfMemory) => {
+            return Err(format!("returned a block of size {} that doesn't fit in the memory", size));
+        }
+        (Op::Alloc { id, size }, Effect::Address(address)) => {
+            check_placement(shadow, id, address, size)?;
+            shadow.insert(id, ShadowBlock { address, size, words: HashMap::new() });
+        }
+        (Op::Realloc { id, size }, Effect::Address(address)) => {
+            check_placement(shadow, id, address, size)?;
+            if let Some(block) = shadow.get_mut(&id) {
+                block.address = address;
+                block.size = size;
+                block.words.retain(|&offset, _| offset + 4 <= size);
+            }
+        }
+        (Op::Dealloc { id }, _) => {
+            shadow.remove(&id);
+        }
+        (Op::Store { id, offset, value }, _) => {
+            if let Some(block) = shadow.get_mut(&id) {
+                block.words.insert(offset, value);
+            }
+        }
+        (Op::Load { id, offset }, Effect::Value(value)) => {
+            let expected = shadow.get(&id).and_then(|block| block.words.get(&offset));
+            if let Some(&expected) = expected {
+                if value != expected {
+                    return Err(format!("loaded {:#x}, expected {:#x}", value, expected));
+                }
+            }
+        }
+        _ => {}
+    }
+    verify(&mut runner.allocator, shadow)
+}
+
+// check runs the operations against the allocator and returns the first bug.
+// A trap whose message contains one of `allowed_traps` ends the run without a
+// bug.
+pub fn check(allocator: WasmAllocator, ops: &[Op], allowed_traps: &[String]) -> Option<Bug> {
+    let mut runner = Runner::new(allocator);
+    let mut shadow = HashMap::new();
+    for (index, op) in ops.iter().enumerate() {
+        if let Err(message) = step(&mut runner, &mut shadow, op) {
+            if message.starts_with("trap: ") && allowed_traps.iter().any(|text| message.contains(text.as_str())) {
+                return None;
+            }
+            return Some(Bug { index, message });
+        }
+    }
+    None
+}
+
+// fingerprint returns a stable hash of the input (FNV-1a) used to name saved
+// inputs.
+fn fingerprint(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// report prints a bug along with the operations that led to it.
+fn report(ops: &[Op], bug: &Bug) {
+    println!("Bug at operation {} ({}): {}", bug.index, ops[bug.index], bug.message);
+    println!("Operations:");
+    for (i, op) in ops.iter().enumerate().take(bug.index + 1) {
+        println!("  {:6}  {}", i, op);
+    }
+}
+
+// Options configures a fuzzing run.
+pub struct Options {
+    pub seed: u64,
+    pub iterations: u64,
+    // max_len is the maximum length of the random inputs in bytes.
+    pub max_len: usize,
+    pub allowed_traps: Vec<String>,
+}
+
+// run replays the given input files against the module, or fuzzes it with
+// random inputs if there are none. The first input that finds a bug is saved
+// as `crash-<hash>` in the current directory so it can be replayed. It
+// returns whether no bug was found.
+pub fn run(module: &str, inputs: &[String], options: &Options) -> Result<bool, Box<dyn Error>> {
+    let engine = WasmAllocator::engine()?;
+    let compiled = Module::from_file(&engine, module)?;
+    let mut rng = Rng::new(options.seed);
+    let count = if inputs.is_empty() { options.iterations } else { inputs.len() as u64 };
+    for i in 0..count {
+        let data = match inputs.get(i as usize) {
+            Some(path) => fs::read(path).map_err(|err| format!("{}: {}", path, err))?,
+            None => {
+                let len = rng.below(options.max_len as u64 + 1) as usize;
+                (0..len).map(|_| rng.next_u64() as u8).collect()
+            }
+        };
+        let ops = decode(&data);
+        if let Some(bug) = check(WasmAllocator::instantiate(&engine, &compiled)?, &ops, &options.allowed_traps) {
+            report(&ops, &bug);
+            let path = format!("crash-{:016x}", fingerprint(&data));
+            fs::write(&path, &data)?;
+            println!("Input saved to {}. Replay it with 'cargo run -- fuzz {} {}'", path, module, path);
+            return Ok(false);
+        }
+    }
+    println!("No bugs found in {} input(s)", count);
+    Ok(true)
+}
@@ -0,0 +1,267 @@
+use crate::allocator::{self, Block, WasmAllocator};
+use crate::ops::{event, trapped, Op, Runner, PAGE_SIZE};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+// The heap checker walks an allocator's blocks through the heap walk exports
+// and verifies the structure of its heap:
+//
+//   - blocks lie inside the memory, in order, without overlapping
+//   - blocks tile the heap: every gap between blocks is a header of the same
+//     size as the one before the first block
+//   - block addresses and sizes are aligned
+//   - the free list, if exported, links exactly the free blocks, once each
+//   - no two adjacent blocks are free, if the allocator says it coalesces
+//   - every live block the host knows of is a used block large enough for it
+//
+// Modules without the heap walk exports can't be checked.
+
+// CONTEXT is the number of blocks shown on each side of a broken invariant.
+const CONTEXT: usize = 2;
+
+// Problem is a broken invariant, with the blocks around it.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub message: String,
+    // around holds the blocks around the problem, with the index of the one
+    // it's about, if any.
+    pub around: Vec<Block>,
+    pub at: Option<usize>,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if !self.around.is_empty() {
+            write!(f, "\n  blocks around it:")?;
+        }
+        for (i, block) in self.around.iter().enumerate() {
+            let end = block.address + block.size;
+            write!(
+                f,
+                "\n  {} [{:#010x}, {:#010x}) {:>8} bytes {}",
+                if Some(i) == self.at { "->" } else { "  " },
+                block.address,
+                end,
+                block.size,
+                if block.free { "free" } else { "used" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// problem returns a problem about the block at index `at` of the walk.
+fn problem(message: String, walk: &[Block], at: usize) -> Problem {
+    let start = at.saturating_sub(CONTEXT);
+    let end = (at + CONTEXT + 1).min(walk.len());
+    Problem { message, around: walk[start..end].to_vec(), at: Some(at - start) }
+}
+
+// Schedule sets how often a session checks the heap, and the alignment of
+// blocks.
+#[derive(Debug, Clone, Copy)]
+pub struct Schedule {
+    pub every: usize,
+    pub align: u64,
+}
+
+// check verifies the heap of the module, given the live blocks the host knows
+// of as (address, requested size) and the alignment of blocks. It returns the
+// problems found, or None if the module doesn't export the heap walk.
+pub fn check(allocator: &mut WasmAllocator, live: &[(u64, u64)], align: u64) -> Result<Option<Vec<Problem>>, allocator::Error> {
+    let walk = match allocator.walk()? {
+        Some(walk) => walk,
+        None => return Ok(None),
+    };
+    let heap = Heap {
+        walk,
+        free_list: allocator.free_list()?,
+        coalesces: allocator.coalesces()?,
+        memory: allocator.memory_pages()? as u64 * PAGE_SIZE,
+    };
+    Ok(Some(heap.problems(live, align)))
+}
+
+// Heap is what the heap walk exports tell about the heap of a module.
+struct Heap {
+    walk: Vec<Block>,
+    // free_list holds the addresses in the free list, if it's exported.
+    free_list: Option<Vec<u64>>,
+    coalesces: bool,
+    // memory is the size of the memory in bytes.
+    memory: u64,
+}
+
+impl Heap {
+    // problems verifies the heap like check.
+    fn problems(&self, live: &[(u64, u64)], align: u64) -> Vec<Problem> {
+        let (walk, memory) = (&self.walk, self.memory);
+        let mut problems = Vec::new();
+
+        // header is the size of the gap before the first block, which every
+        // block should have in front of it.
+        let header = walk.first().map_or(0, |block| block.address);
+        let mut cursor = 0;
+        for (i, block) in walk.iter().enumerate() {
+            let end = block.address + block.size;
+            if end > memory {
+                problems.push(problem(format!("block {:#x} ends at {:#x}, past the end of the memory at {:#x}", block.address, end, memory), walk, i));
+            }
+            if i > 0 && block.address < cursor {
+                problems.push(problem(format!("block {:#x} overlaps the block before it, which ends at {:#x}", block.address, cursor), walk, i));
+            } else if i > 0 && block.address - cursor != header {
+                problems.push(problem(
+                    format!("block {:#x} is {} bytes after the block before it, but headers are {} bytes", block.address, block.address - cursor, header),
+                    walk,
+                    i,
+                ));
+            }
+            if block.address % align.max(1) != 0 || block.size % align.max(1) != 0 {
+                problems.push(problem(format!("block {:#x} of {} bytes isn't aligned to {} bytes", block.address, block.size, align), walk, i));
+            }
+            cursor = cursor.max(end);
+        }
+
+        let index: HashMap<u64, usize> = walk.iter().enumerate().map(|(i, block)| (block.address, i)).collect();
+        if let Some(free_list) = &self.free_list {
+            let mut listed = HashSet::new();
+            for &address in free_list {
+                match index.get(&address) {
+                    _ if !listed.insert(address) => {
+                        problems.push(Problem { message: format!("the free list loops back to {:#x}", address), around: Vec::new(), at: None });
+                    }
+                    Some(&i) if !walk[i].free => {
+                        problems.push(problem(format!("the free list links to block {:#x}, which is used", address), walk, i));
+                    }
+                    Some(_) => {}
+                    None => {
+                        problems.push(Problem { message: format!("the free list links to {:#x}, which isn't a block", address), around: Vec::new(), at: None });
+                    }
+                }
+            }
+            for (i, block) in walk.iter().enumerate().filter(|(_, block)| block.free && !listed.contains(&block.address)) {
+                problems.push(problem(format!("free block {:#x} isn't in the free list", block.address), walk, i));
+            }
+        }
+
+        if self.coalesces {
+            for (i, pair) in walk.windows(2).enumerate() {
+                if pair[0].free && pair[1].free {
+                    problems.push(problem(format!("free blocks {:#x} and {:#x} weren't coalesced", pair[0].address, pair[1].address), walk, i));
+                }
+            }
+        }
+
+        for &(address, size) in live {
+            match index.get(&address) {
+                Some(&i) if walk[i].free => {
+                    problems.push(problem(format!("live block {:#x} is marked free", address), walk, i));
+                }
+                Some(&i) if walk[i].size < size => {
+                    problems.push(problem(format!("live block {:#x} has {} bytes, but {} were requested", address, walk[i].size, size), walk, i));
+                }
+                Some(_) => {}
+                None => {
+                    // Show the blocks around where the live block should be.
+                    let at = walk.partition_point(|block| block.address < address).min(walk.len().saturating_sub(1));
+                    let message = format!("live block {:#x} of {} bytes isn't a block of the heap", address, size);
+                    problems.push(if walk.is_empty() { Problem { message, around: Vec::new(), at: None } } else { problem(message, walk, at) });
+                }
+            }
+        }
+        problems
+    }
+}
+
+// Report is the result of checking a workload.
+#[derive(Debug)]
+pub struct Report {
+    pub checks: usize,
+    // failure is the index of the operation after which the first problems
+    // were found, with the problems.
+    pub failure: Option<(usize, Vec<Problem>)>,
+}
+
+// run replays the operations against the module at `path` and checks its heap
+// after every `every` operations and after the last one, stopping at the
+// first check that finds problems.
+pub fn run(path: &str, ops: &[Op], every: usize, align: u64) -> Result<Report, Box<dyn Error>> {
+    let mut runner = Runner::new(WasmAllocator::new(path)?);
+    let mut report = Report { checks: 0, failure: None };
+    for (index, op) in ops.iter().enumerate() {
+        runner.step(index, op)?;
+        if (index + 1) % every.max(1) != 0 && index + 1 != ops.len() {
+            continue;
+        }
+        let live = runner.live_sizes();
+        let problems = check(&mut runner.allocator, &live, align)
+            .map_err(|err| trapped(&format!("heap walk after {}", event(index, op)), err))?
+            .ok_or_else(|| format!("{} doesn't export the heap walk functions, so its heap can't be checked", path))?;
+        report.checks += 1;
+        if !problems.is_empty() {
+            report.failure = Some((index, problems));
+            break;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(address: u64, size: u64, free: bool) -> Block {
+        Block { address, size, free }
+    }
+
+    fn messages(heap: &Heap, live: &[(u64, u64)]) -> Vec<String> {
+        heap.problems(live, 4).iter().map(|problem| problem.message.clone()).collect()
+    }
+
+    #[test]
+    fn consistent_heap() {
+        let walk = vec![block(4, 12, false), block(20, 8, true), block(32, 32, false)];
+        let heap = Heap { walk, free_list: Some(vec![20]), coalesces: true, memory: 64 };
+        assert!(messages(&heap, &[(4, 10), (32, 32)]).is_empty());
+    }
+
+    #[test]
+    fn overlap() {
+        let walk = vec![block(4, 16, false), block(12, 8, false), block(24, 8, false)];
+        let heap = Heap { walk, free_list: None, coalesces: false, memory: 64 };
+        let problems = heap.problems(&[], 4);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].message, "block 0xc overlaps the block before it, which ends at 0x14");
+        assert_eq!(problems[0].at, Some(1));
+        assert_eq!(problems[0].around.len(), 3);
+    }
+
+    #[test]
+    fn uncoalesced_pair() {
+        let walk = vec![block(4, 8, false), block(16, 8, true), block(28, 8, true)];
+        let mut heap = Heap { walk, free_list: Some(vec![16, 28]), coalesces: false, memory: 64 };
+        assert!(messages(&heap, &[]).is_empty());
+        heap.coalesces = true;
+        assert_eq!(messages(&heap, &[]), ["free blocks 0x10 and 0x1c weren't coalesced"]);
+    }
+
+    #[test]
+    fn free_list_and_live_blocks() {
+        let walk = vec![block(4, 8, true), block(16, 8, false), block(28, 8, true)];
+        let heap = Heap { walk, free_list: Some(vec![16, 16]), coalesces: false, memory: 64 };
+        assert_eq!(
+            messages(&heap, &[(4, 8), (16, 12), (40, 4)]),
+            [
+                "the free list links to block 0x10, which is used",
+                "the free list loops back to 0x10",
+                "free block 0x4 isn't in the free list",
+                "free block 0x1c isn't in the free list",
+                "live block 0x4 is marked free",
+                "live block 0x10 has 8 bytes, but 12 were requested",
+                "live block 0x28 of 4 bytes isn't a block of the heap",
+            ]
+        );
+    }
+}
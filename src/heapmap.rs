@@ -0,0 +1,415 @@
+use crate::allocator::{Block, WasmAllocator};
+use crate::ops::{event, trapped, Effect, Op, Runner, PAGE_SIZE};
+use crate::svg::escape;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt::Write;
+
+// A heap map shows what each part of the linear memory is used for at a point
+// of a workload. The host only knows the blocks it was given, so memory is
+// classified from their history: memory in a live block is live, memory that
+// was in a block that's been freed is free, other memory below the highest
+// block ever allocated holds the allocator's headers, metadata or padding, and
+// memory above it is untouched. If the allocator exports the heap walk
+// functions, its own blocks are used instead, which tells free blocks, headers
+// and padding apart exactly.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Live,
+    Free,
+    Overhead,
+    Untouched,
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Live => "live",
+            Kind::Free => "free",
+            Kind::Overhead => "overhead",
+            Kind::Untouched => "untouched",
+        }
+    }
+}
+
+// Region is a range of memory of one kind. Live regions are single blocks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub start: u64,
+    pub end: u64,
+    pub kind: Kind,
+}
+
+// Layout is the classification of the whole memory, as sorted regions.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    // index is the index of the operation after which the layout was taken.
+    pub index: usize,
+    pub memory: u64,
+    pub regions: Vec<Region>,
+    // walked is whether the regions come from the allocator's heap walk.
+    pub walked: bool,
+}
+
+impl Layout {
+    // from_walk classifies the memory from the allocator's blocks, given the
+    // requested size of the live blocks by address. Used blocks are live up to
+    // the requested size and padding past it, free blocks are free, memory
+    // before and between blocks holds headers and metadata, and memory past
+    // the last block is untouched.
+    pub fn from_walk(index: usize, walk: &[Block], live: &HashMap<u64, u64>, memory: u64) -> Layout {
+        let mut regions: Vec<Region> = Vec::new();
+        let mut push = |start: u64, end: u64, kind: Kind| {
+            let end = end.min(memory);
+            if start >= end {
+                return;
+            }
+            match regions.last_mut() {
+                Some(last) if last.kind == kind && kind != Kind::Live => last.end = end,
+                _ => regions.push(Region { start, end, kind }),
+            }
+        };
+        // cursor is the end of the last block so far.
+        let mut cursor = 0;
+        for block in walk {
+            push(cursor, block.address, Kind::Overhead);
+            let end = block.address + block.size;
+            if block.free {
+                push(block.address, end, Kind::Free);
+            } else {
+                let requested = live.get(&block.address).map_or(block.size, |&size| size.min(block.size));
+                push(block.address, block.address + requested, Kind::Live);
+                push(block.address + requested, end, Kind::Overhead);
+            }
+            cursor = cursor.max(end);
+        }
+        push(cursor, memory, Kind::Untouched);
+        Layout { index, memory, regions, walked: true }
+    }
+}
+
+// covers returns whether any of the ranges, keyed by start, covers `at`.
+fn covers(ranges: &BTreeMap<u64, u64>, at: u64) -> bool {
+    ranges.range(..=at).next_back().is_some_and(|(_, &end)| at < end)
+}
+
+// History tracks the blocks of a workload, to classify memory.
+#[derive(Default)]
+pub struct History {
+    // touched holds the disjoint ranges that have been in a block, by start.
+    touched: BTreeMap<u64, u64>,
+    high_water: u64,
+}
+
+impl History {
+    // place records a block returned by alloc or realloc.
+    pub fn place(&mut self, address: u64, size: u64) {
+        if size == 0 {
+            return;
+        }
+        let (mut start, mut end) = (address, address + size);
+        // Merge the ranges that overlap or touch the block.
+        let merged: Vec<(u64, u64)> = self
+            .touched
+            .range(..=end)
+            .filter(|(_, &range_end)| range_end >= start)
+            .map(|(&range_start, &range_end)| (range_start, range_end))
+            .collect();
+        for (range_start, range_end) in merged {
+            self.touched.remove(&range_start);
+            start = start.min(range_start);
+            end = end.max(range_end);
+        }
+        self.touched.insert(start, end);
+        self.high_water = self.high_water.max(end);
+    }
+
+    // layout classifies the memory given the live blocks as (address, size).
+    pub fn layout(&self, index: usize, live: &[(u64, u64)], memory: u64) -> Layout {
+        let live: BTreeMap<u64, u64> =
+            live.iter().filter(|(_, size)| *size > 0).map(|&(address, size)| (address, address + size)).collect();
+        let mut boundaries: BTreeSet<u64> = [0, memory, self.high_water.min(memory)].into_iter().collect();
+        for (&start, &end) in live.iter().chain(&self.touched) {
+            boundaries.insert(start.min(memory));
+            boundaries.insert(end.min(memory));
+        }
+        let boundaries: Vec<u64> = boundaries.into_iter().collect();
+        let mut regions: Vec<Region> = Vec::new();
+        for pair in boundaries.windows(2) {
+            let (start, end) = (pair[0], pair[1]);
+            let kind = if covers(&live, start) {
+                Kind::Live
+            } else if covers(&self.touched, start) {
+                Kind::Free
+            } else if start < self.high_water {
+                Kind::Overhead
+            } else {
+                Kind::Untouched
+            };
+            match regions.last_mut() {
+                // Keep live blocks apart, and merge everything else.
+                Some(last) if last.kind == kind && kind != Kind::Live => last.end = end,
+                _ => regions.push(Region { start, end, kind }),
+            }
+        }
+        Layout { index, memory, regions, walked: false }
+    }
+}
+
+// capture replays the operations against the module at `path` and returns the
+// layout after each of the given operation indices, in order. It stops after
+// the last of them, so later traps don't matter.
+pub fn capture(path: &str, ops: &[Op], at: &[usize]) -> Result<Vec<Layout>, Box<dyn Error>> {
+    let mut runner = Runner::new(WasmAllocator::new(path)?);
+    let mut history = History::default();
+    let mut layouts = Vec::new();
+    let last = at.iter().max().map_or(0, |last| last + 1);
+    for (index, op) in ops.iter().enumerate().take(last) {
+        if let (Op::Alloc { size, .. } | Op::Realloc { size, .. }, Effect::Address(address)) = (*op, runner.step(index, op)?) {
+            history.place(address as u32 as u64, size as u32 as u64);
+        }
+        if at.contains(&index) {
+            let live = runner.live_sizes();
+            let memory = runner.allocator.memory_pages()? as u64 * PAGE_SIZE;
+            let walk = runner.allocator.walk().map_err(|err| trapped(&format!("heap walk after {}", event(index, op)), err))?;
+            layouts.push(match walk {
+                Some(walk) => Layout::from_walk(index, &walk, &live.into_iter().collect(), memory),
+                None => history.layout(index, &live, memory),
+            });
+        }
+    }
+    Ok(layouts)
+}
+
+// source describes where the regions of the layout come from.
+fn source(layout: &Layout) -> &'static str {
+    if layout.walked { "from the heap walk" } else { "from the host's view" }
+}
+
+// extent returns the end of the part of the memory worth showing: up to the
+// end of the last region that isn't untouched, rounded up to a full row.
+fn extent(layout: &Layout, row: u64) -> u64 {
+    let used = layout.regions.iter().filter(|region| region.kind != Kind::Untouched).map(|region| region.end).max();
+    used.unwrap_or(0).div_ceil(row).max(1) * row
+}
+
+// map_end returns where a map of the layout with rows of `row` bytes ends: at
+// the end of the used memory, or of the memory if it's smaller.
+fn map_end(layout: &Layout, row: u64) -> u64 {
+    extent(layout, row).min(layout.memory.div_ceil(row) * row)
+}
+
+// rows returns the number of rows of a map of the layout.
+pub fn rows(layout: &Layout, width: u64, cell: u64) -> u64 {
+    map_end(layout, width * cell) / (width * cell)
+}
+
+// cell_size picks the bytes per cell so that the used memory fits in about
+// `rows` rows of `width` cells, as a power of two.
+pub fn cell_size(layout: &Layout, width: u64, rows: u64) -> u64 {
+    let used = extent(layout, 1);
+    used.div_ceil(width * rows).max(1).next_power_of_two()
+}
+
+// ascii renders the layout as text, a row of `width` cells per line, with
+// each cell showing the kind of memory covering most of its `cell` bytes.
+// Neighboring live blocks alternate between `#` and `=` to tell them apart.
+pub fn ascii(layout: &Layout, width: u64, cell: u64) -> String {
+    let row = width * cell;
+    let end = map_end(layout, row);
+    let mut text = format!(
+        "after op {}: {} bytes ({} pages), {} bytes per cell, {}\n",
+        layout.index,
+        layout.memory,
+        layout.memory / PAGE_SIZE,
+        cell,
+        source(layout)
+    );
+    // Live blocks are numbered so that neighbors alternate.
+    let mut live_number = 0;
+    let symbols: Vec<(Region, char)> = layout
+        .regions
+        .iter()
+        .map(|region| {
+            let symbol = match region.kind {
+                Kind::Live => {
+                    live_number += 1;
+                    if live_number % 2 == 1 { '#' } else { '=' }
+                }
+                Kind::Free => '.',
+                Kind::Overhead => '+',
+                Kind::Untouched => ' ',
+            };
+            (*region, symbol)
+        })
+        .collect();
+    // first is the first region that may overlap the current cell.
+    let mut first = 0;
+    for row_start in (0..end).step_by(row as usize) {
+        let _ = write!(text, "{:08x} |", row_start);
+        for cell_start in (row_start..row_start + row).step_by(cell as usize) {
+            let cell_end = cell_start + cell;
+            while symbols.get(first).is_some_and(|(region, _)| region.end <= cell_start) {
+                first += 1;
+            }
+            // Pick the region that covers the most of the cell.
+            let symbol = symbols[first..]
+                .iter()
+                .take_while(|(region, _)| region.start < cell_end)
+                .max_by_key(|(region, _)| region.end.min(cell_end) - region.start.max(cell_start))
+                .map_or(' ', |(_, symbol)| *symbol);
+            text.push(symbol);
+        }
+        text.push_str("|\n");
+    }
+    if end < layout.memory {
+        let _ = writeln!(text, "{:08x} | untouched up to {:#x}", end, layout.memory);
+    }
+    text + "legend: # and = live blocks, . free, + headers/metadata/padding, blank untouched"
+}
+
+// The fill colors of the kinds of memory, with two for live blocks to tell
+// neighbors apart.
+const LIVE_COLORS: [&str; 2] = ["#1f77b4", "#4a9bd4"];
+const FREE_COLOR: &str = "#98df8a";
+const OVERHEAD_COLOR: &str = "#ff7f0e";
+const UNTOUCHED_COLOR: &str = "#eeeeee";
+
+// CELL_PIXELS is the width of a cell in the SVG, and ROW_PIXELS the height of
+// a row.
+const CELL_PIXELS: f64 = 8.0;
+const ROW_PIXELS: f64 = 14.0;
+
+// svg renders the layouts as an SVG image, one map below the other, laid out
+// like the text rendering. Hovering a region shows its address range. Maps
+// have as many rows as they need, or `rows` if set, so that images of
+// different layouts line up.
+pub fn svg(layouts: &[Layout], width: u64, cell: u64, rows: Option<u64>) -> String {
+    let row = width * cell;
+    let left = 80.0;
+    let map_width = width as f64 * CELL_PIXELS;
+    let mut body = String::new();
+    let mut y = 10.0;
+    for layout in layouts {
+        let end = rows.map_or_else(|| map_end(layout, row), |rows| rows * row);
+        let _ = writeln!(
+            body,
+            "<text x=\"{}\" y=\"{}\" font-weight=\"bold\">after op {}: {} bytes ({} pages), {} bytes per cell, {}</text>",
+            left,
+            y + 12.0,
+            layout.index,
+            layout.memory,
+            layout.memory / PAGE_SIZE,
+            cell,
+            source(layout)
+        );
+        y += 20.0;
+        let top = y;
+        for row_start in (0..end).step_by(row as usize) {
+            let _ = writeln!(
+                body,
+                "<text x=\"{}\" y=\"{}\" text-anchor=\"end\" font-family=\"monospace\">{:08x}</text>",
+                left - 5.0,
+                top + (row_start / row) as f64 * ROW_PIXELS + 10.0,
+                row_start
+            );
+        }
+        let mut live_number = 0;
+        for region in layout.regions.iter().filter(|region| region.start < end) {
+            let color = match region.kind {
+                Kind::Live => {
+                    live_number += 1;
+                    LIVE_COLORS[live_number % 2]
+                }
+                Kind::Free => FREE_COLOR,
+                Kind::Overhead => OVERHEAD_COLOR,
+                Kind::Untouched => UNTOUCHED_COLOR,
+            };
+            let title = format!("{} [{:#x}, {:#x}) {} bytes", region.kind.name(), region.start, region.end, region.end - region.start);
+            // A region can span several rows, so draw a rectangle per row.
+            let mut start = region.start;
+            let region_end = region.end.min(end);
+            while start < region_end {
+                let row_end = (start / row + 1) * row;
+                let piece_end = region_end.min(row_end);
+                let _ = writeln!(
+                    body,
+                    "<rect x=\"{:.2}\" y=\"{:.1}\" width=\"{:.2}\" height=\"{}\" fill=\"{}\"><title>{}</title></rect>",
+                    left + (start % row) as f64 / cell as f64 * CELL_PIXELS,
+                    top + (start / row) as f64 * ROW_PIXELS,
+                    (piece_end - start) as f64 / cell as f64 * CELL_PIXELS,
+                    ROW_PIXELS - 2.0,
+                    color,
+                    escape(&title)
+                );
+                start = piece_end;
+            }
+        }
+        y = top + (end / row) as f64 * ROW_PIXELS + 15.0;
+    }
+    let legend = [("live", LIVE_COLORS[1]), ("free", FREE_COLOR), ("headers/metadata/padding", OVERHEAD_COLOR), ("untouched", UNTOUCHED_COLOR)];
+    let mut x = left;
+    for (name, color) in legend {
+        let _ = writeln!(
+            body,
+            "<rect x=\"{}\" y=\"{}\" width=\"10\" height=\"10\" fill=\"{}\"/><text x=\"{}\" y=\"{}\">{}</text>",
+            x,
+            y,
+            color,
+            x + 14.0,
+            y + 9.0,
+            name
+        );
+        x += 30.0 + name.len() as f64 * 6.0;
+    }
+    y += 20.0;
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"11\">\n{}</svg>\n",
+        left + map_width + 20.0,
+        y,
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touched(history: &History) -> Vec<(u64, u64)> {
+        history.touched.iter().map(|(&start, &end)| (start, end)).collect()
+    }
+
+    #[test]
+    fn place_merges_overlapping_and_touching_ranges() {
+        let mut history = History::default();
+        history.place(100, 20);
+        history.place(200, 20);
+        history.place(0, 0);
+        assert_eq!(touched(&history), [(100, 120), (200, 220)]);
+        // Touching on either side.
+        history.place(120, 10);
+        history.place(90, 10);
+        assert_eq!(touched(&history), [(90, 130), (200, 220)]);
+        // Inside an existing range.
+        history.place(95, 5);
+        assert_eq!(touched(&history), [(90, 130), (200, 220)]);
+        // Spanning both ranges and beyond.
+        history.place(80, 160);
+        assert_eq!(touched(&history), [(80, 240)]);
+        history.place(300, 8);
+        assert_eq!(touched(&history), [(80, 240), (300, 308)]);
+        assert_eq!(history.high_water, 308);
+    }
+
+    #[test]
+    fn layout_classifies_the_memory() {
+        let mut history = History::default();
+        history.place(16, 16);
+        history.place(48, 16);
+        let layout = history.layout(3, &[(48, 16)], 128);
+        let regions: Vec<(u64, u64, Kind)> = layout.regions.iter().map(|region| (region.start, region.end, region.kind)).collect();
+        assert_eq!(regions, [(0, 16, Kind::Overhead), (16, 32, Kind::Free), (32, 48, Kind::Overhead), (48, 64, Kind::Live), (64, 128, Kind::Untouched)]);
+        assert!(!layout.walked);
+    }
+}
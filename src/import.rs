@@ -0,0 +1,310 @@
+use crate::ops::Op;
+use crate::trace::Recorder;
+use std::collections::HashMap;
+
+// Importers convert allocation traces produced by common Linux tools into
+// trace events, so that real programs can be replayed against the allocators.
+// Every format reports real addresses, which a Recorder maps to logical ids.
+
+// Format is a supported trace format.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // glibc's mtrace output, written to $MALLOC_TRACE.
+    Mtrace,
+    // The log of `ltrace -e malloc+calloc+realloc+free`.
+    Ltrace,
+    // heaptrack's line-based data file, raw or interpreted.
+    Heaptrack,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "mtrace" => Some(Format::Mtrace),
+            "ltrace" => Some(Format::Ltrace),
+            "heaptrack" => Some(Format::Heaptrack),
+            _ => None,
+        }
+    }
+}
+
+// import converts the text of a trace in the given format.
+pub fn import(format: Format, text: &str) -> Result<Vec<Op>, String> {
+    match format {
+        Format::Mtrace => import_mtrace(text),
+        Format::Ltrace => import_ltrace(text),
+        Format::Heaptrack => import_heaptrack(text),
+    }
+}
+
+// parse_number parses a decimal number or a `0x`-prefixed hexadecimal one.
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u64> {
+    u64::from_str_radix(text.trim_start_matches("0x"), 16).ok()
+}
+
+// import_mtrace imports glibc's mtrace output, which looks like this:
+//
+//     = Start
+//     @ ./prog:[0x4005c7] + 0x1c4a460 0x64
+//     @ ./prog:[0x4005e3] < 0x1c4a460
+//     @ ./prog:[0x4005e3] > 0x1c4a8d0 0xc8
+//     @ ./prog:[0x4005d5] - 0x1c4a8d0
+//     = End
+//
+// `+` is an allocation with its address and size, `-` a free, and a realloc
+// is a `<` line with the old address followed by a `>` line with the new
+// address and size. The `@ caller` part is optional.
+fn import_mtrace(text: &str) -> Result<Vec<Op>, String> {
+    let mut recorder = Recorder::new();
+    // realloc_from holds the old address of a realloc between its two lines.
+    let mut realloc_from = None;
+    for (i, line) in text.lines().enumerate() {
+        let mut fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.first() == Some(&"@") {
+            fields.drain(..fields.len().min(2));
+        }
+        let invalid = || format!("line {}: invalid mtrace event '{}'", i + 1, line);
+        let number = |field: &str| parse_hex(field).ok_or_else(invalid);
+        match fields[..] {
+            ["+", address, size] => recorder.alloc(number(size)?, number(address)?),
+            ["-", address] => recorder.dealloc(number(address)?),
+            ["<", address] => realloc_from = Some(number(address)?),
+            [">", address, size] => {
+                let old = realloc_from.take().ok_or_else(invalid)?;
+                recorder.realloc(old, number(size)?, number(address)?);
+            }
+            // Start and end markers and anything unknown.
+            _ => {}
+        }
+    }
+    Ok(recorder.ops)
+}
+
+// Call is a call to an allocation function in an ltrace log.
+struct Call {
+    function: String,
+    args: Vec<u64>,
+    // result is the returned address, or zero for NULL and void.
+    result: u64,
+}
+
+// parse_ltrace_result parses a return value such as `0x55d0c8a2e2a0`, `nil`
+// or `<void>`.
+fn parse_ltrace_result(text: &str) -> u64 {
+    parse_number(text.trim()).unwrap_or(0)
+}
+
+// parse_ltrace_args parses comma-separated arguments, where pointers can be
+// NULL (`nil` or `0`).
+fn parse_ltrace_args(text: &str) -> Vec<u64> {
+    text.split(',').map(|arg| parse_number(arg.trim()).unwrap_or(0)).collect()
+}
+
+// import_ltrace imports the log of `ltrace -e malloc+calloc+realloc+free`,
+// optionally with `-f` (pid prefixes) and timestamps:
+//
+//     prog->malloc(24)                          = 0x55d0c8a2e2a0
+//     [pid 42] prog->realloc(0x55d0c8a2e2a0, 48) = 0x55d0c8a2e6d0
+//     prog->free(0x55d0c8a2e6d0 <unfinished ...>
+//     <... free resumed> )                      = <void>
+//
+// Calls interrupted by other threads are joined with their resumed lines.
+fn import_ltrace(text: &str) -> Result<Vec<Op>, String> {
+    const FUNCTIONS: [&str; 4] = ["malloc", "calloc", "realloc", "free"];
+    let mut recorder = Recorder::new();
+    // unfinished holds the function and arguments of interrupted calls by pid.
+    let mut unfinished: HashMap<String, (String, String)> = HashMap::new();
+    for line in text.lines() {
+        let (pid, line) = match line.strip_prefix("[pid ") {
+            Some(rest) => match rest.split_once(']') {
+                Some((pid, rest)) => (pid.to_string(), rest.trim()),
+                None => continue,
+            },
+            None => (String::new(), line.trim()),
+        };
+        let call = if let Some(rest) = line.strip_prefix("<... ") {
+            // A resumed call: `<... malloc resumed> ) = 0x1234`.
+            let (function, args) = match unfinished.remove(&pid) {
+                Some(pending) => pending,
+                None => continue,
+            };
+            let result = rest.rsplit_once(" = ").map(|(_, result)| result).unwrap_or("");
+            Call { function, args: parse_ltrace_args(&args), result: parse_ltrace_result(result) }
+        } else {
+            // A call such as `prog->malloc(24) = 0x1234`, possibly preceded
+            // by a timestamp and the calling library.
+            let found = FUNCTIONS.iter().find_map(|function| {
+                let start = line.find(&format!("{}(", function))?;
+                let boundary = line[..start].chars().last().is_none_or(|c| c == '>' || c.is_whitespace());
+                boundary.then(|| (function.to_string(), &line[start + function.len() + 1..]))
+            });
+            let (function, rest) = match found {
+                Some(found) => found,
+                None => continue,
+            };
+            if let Some((args, _)) = rest.split_once(" <unfinished") {
+                unfinished.insert(pid, (function, args.to_string()));
+                continue;
+            }
+            let (args, result) = match rest.rsplit_once(" = ") {
+                Some((args, result)) => (args.trim_end().trim_end_matches(')'), result),
+                None => continue,
+            };
+            Call { function, args: parse_ltrace_args(args), result: parse_ltrace_result(result) }
+        };
+        match (call.function.as_str(), &call.args[..]) {
+            // Failed allocations don't allocate anything.
+            ("malloc" | "calloc" | "realloc", _) if call.result == 0 => {
+                // realloc(ptr, 0) frees ptr and may return NULL.
+                if let ("realloc", [address, 0]) = (call.function.as_str(), &call.args[..]) {
+                    recorder.dealloc(*address);
+                }
+            }
+            ("malloc", [size]) => recorder.alloc(*size, call.result),
+            ("calloc", [count, size]) => recorder.alloc(count.saturating_mul(*size), call.result),
+            ("realloc", [address, size]) => recorder.realloc(*address, *size, call.result),
+            ("free", [address]) => recorder.dealloc(*address),
+            _ => return Err(format!("unexpected arguments in '{}'", line)),
+        }
+    }
+    Ok(recorder.ops)
+}
+
+// import_heaptrack imports heaptrack's data file (after decompressing it).
+// Numbers are hexadecimal. Raw files record allocations as `+ size trace
+// address` and frees as `- address`. Files written by heaptrack_interpret
+// instead define allocation infos as `a size trace` lines, and record
+// allocations and frees as `+ info` and `- info` by info index. As those
+// don't say which block is freed, the most recent live block with the same
+// info is. Reallocs show up as a free followed by an allocation. All other
+// lines are ignored.
+fn import_heaptrack(text: &str) -> Result<Vec<Op>, String> {
+    let mut recorder = Recorder::new();
+    // sizes holds the size of each allocation info, by index.
+    let mut sizes: Vec<u64> = Vec::new();
+    // live holds stand-in addresses of the live blocks of each info.
+    let mut live: HashMap<u64, Vec<u64>> = HashMap::new();
+    let mut next_address = 1;
+    let mut raw = false;
+    for (i, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let invalid = || format!("line {}: invalid heaptrack event '{}'", i + 1, line);
+        let number = |field: &str| parse_hex(field).ok_or_else(invalid);
+        match fields[..] {
+            ["a", size, _trace] => sizes.push(number(size)?),
+            ["+", size, _trace, address] => {
+                raw = true;
+                recorder.alloc(number(size)?, number(address)?);
+            }
+            ["-", address] if raw => recorder.dealloc(number(address)?),
+            ["+", info] => {
+                let info = number(info)?;
+                let size = *sizes.get(info as usize).ok_or_else(invalid)?;
+                recorder.alloc(size, next_address);
+                live.entry(info).or_default().push(next_address);
+                next_address += 1;
+            }
+            ["-", info] => {
+                if let Some(address) = live.get_mut(&number(info)?).and_then(Vec::pop) {
+                    recorder.dealloc(address);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(recorder.ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mtrace() {
+        let text = "\
+= Start
+@ ./prog:[0x4005c7] + 0x1c4a460 0x64
++ 0x1c4a500 0x8
+@ ./prog:[0x4005e3] < 0x1c4a460
+@ ./prog:[0x4005e3] > 0x1c4a8d0 0xc8
+@ ./prog:[0x4005d5] - 0x1c4a8d0
+- 0x1c4a500
+- 0xdead
+= End
+";
+        let expected = vec![
+            Op::Alloc { id: 0, size: 100 },
+            Op::Alloc { id: 1, size: 8 },
+            Op::Realloc { id: 0, size: 200 },
+            Op::Dealloc { id: 0 },
+            Op::Dealloc { id: 1 },
+        ];
+        assert_eq!(import(Format::Mtrace, text).unwrap(), expected);
+    }
+
+    #[test]
+    fn mtrace_errors() {
+        assert_eq!(import(Format::Mtrace, "= Start\n+ 0x10 zz\n").unwrap_err(), "line 2: invalid mtrace event '+ 0x10 zz'");
+        assert_eq!(import(Format::Mtrace, "> 0x10 0x8\n").unwrap_err(), "line 1: invalid mtrace event '> 0x10 0x8'");
+    }
+
+    #[test]
+    fn ltrace() {
+        let text = "\
+prog->malloc(24)                          = 0x55d0c8a2e2a0
+[pid 42] prog->calloc(4, 8) = 0x55d0c8a2e400
+[pid 42] prog->realloc(0x55d0c8a2e2a0, 48) = 0x55d0c8a2e6d0
+prog->malloc(1099511627776) = nil
+12:00:01.000000 prog->free(0x55d0c8a2e6d0 <unfinished ...>
+[pid 43] libc.so.6->malloc(16) = 0x55d0c8a2e800
+<... free resumed> )                      = <void>
+[pid 42] prog->realloc(0x55d0c8a2e400, 0) = nil
+prog->free(nil) = <void>
++++ exited (status 0) +++
+";
+        let expected = vec![
+            Op::Alloc { id: 0, size: 24 },
+            Op::Alloc { id: 1, size: 32 },
+            Op::Realloc { id: 0, size: 48 },
+            Op::Alloc { id: 2, size: 16 },
+            Op::Dealloc { id: 0 },
+            Op::Dealloc { id: 1 },
+        ];
+        assert_eq!(import(Format::Ltrace, text).unwrap(), expected);
+    }
+
+    #[test]
+    fn heaptrack_raw() {
+        let text = "v 10100 3\n+ 18 1 1000\n+ 8 2 2000\n- 1000\n- 3000\n- 2000\n";
+        let expected = vec![
+            Op::Alloc { id: 0, size: 24 },
+            Op::Alloc { id: 1, size: 8 },
+            Op::Dealloc { id: 0 },
+            Op::Dealloc { id: 1 },
+        ];
+        assert_eq!(import(Format::Heaptrack, text).unwrap(), expected);
+    }
+
+    #[test]
+    fn heaptrack_interpreted() {
+        // Frees of an info free its most recent live block.
+        let text = "a 18 1\na 10 2\n+ 0\n+ 1\n+ 0\n- 0\n- 1\n- 0\n";
+        let expected = vec![
+            Op::Alloc { id: 0, size: 24 },
+            Op::Alloc { id: 1, size: 16 },
+            Op::Alloc { id: 2, size: 24 },
+            Op::Dealloc { id: 2 },
+            Op::Dealloc { id: 1 },
+            Op::Dealloc { id: 0 },
+        ];
+        assert_eq!(import(Format::Heaptrack, text).unwrap(), expected);
+        assert_eq!(import(Format::Heaptrack, "a 18 1\n+ 1\n").unwrap_err(), "line 2: invalid heaptrack event '+ 1'");
+    }
+}
@@ -0,0 +1,271 @@
+use crate::allocator::{signature, Error, WasmAllocator};
+use std::fmt;
+use std::path::Path;
+use wasmtime::ValType::I32;
+use wasmtime::*;
+
+// The interface validator inspects the exports of an allocator module before
+// it's instantiated, and works out what the harness can do with it. Only
+// alloc, dealloc and realloc are required. The helpers are optional as long as
+// the module exports its memory, which the harness then accesses directly, and
+// the heap walk is optional as a whole.
+
+// Need is how much the harness needs an export.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Need {
+    // The module can't be used without it.
+    Required,
+    // The harness falls back to the exported memory without it.
+    Helper,
+    // Part of the heap walk, which must be exported as a whole or not at all.
+    Walk,
+    // Extends the heap walk, see the convention in allocator.rs.
+    Extra,
+}
+
+// Spec is a function the harness knows how to use.
+#[derive(Debug)]
+pub struct Spec {
+    pub name: &'static str,
+    pub params: &'static [ValType],
+    pub results: &'static [ValType],
+    pub need: Need,
+}
+
+impl Spec {
+    // ty returns the function type the export must have.
+    pub fn ty(&self) -> FuncType {
+        FuncType::new(self.params.iter().cloned(), self.results.iter().cloned())
+    }
+}
+
+// SPECS are the functions the harness knows how to use, in the order they're
+// reported.
+pub const SPECS: &[Spec] = &[
+    Spec { name: "alloc", params: &[I32], results: &[I32], need: Need::Required },
+    Spec { name: "dealloc", params: &[I32], results: &[], need: Need::Required },
+    Spec { name: "realloc", params: &[I32, I32], results: &[I32], need: Need::Required },
+    Spec { name: "store", params: &[I32, I32], results: &[], need: Need::Helper },
+    Spec { name: "load", params: &[I32], results: &[I32], need: Need::Helper },
+    Spec { name: "size", params: &[], results: &[I32], need: Need::Helper },
+    Spec { name: "grow", params: &[I32], results: &[I32], need: Need::Helper },
+    Spec { name: "heap_first", params: &[], results: &[I32], need: Need::Walk },
+    Spec { name: "heap_next", params: &[I32], results: &[I32], need: Need::Walk },
+    Spec { name: "block_size", params: &[I32], results: &[I32], need: Need::Walk },
+    Spec { name: "block_is_free", params: &[I32], results: &[I32], need: Need::Walk },
+    Spec { name: "free_first", params: &[], results: &[I32], need: Need::Extra },
+    Spec { name: "free_next", params: &[I32], results: &[I32], need: Need::Extra },
+    Spec { name: "heap_coalesces", params: &[], results: &[I32], need: Need::Extra },
+];
+
+// spec returns the spec of the function named `name`.
+pub fn spec(name: &str) -> &'static Spec {
+    SPECS.iter().find(|spec| spec.name == name).unwrap_or_else(|| panic!("no spec for {}", name))
+}
+
+// Status is what the module exports under the name of a spec.
+#[derive(Debug, Clone)]
+pub enum Status {
+    Present,
+    Missing,
+    // The export isn't a function of the right type.
+    Mismatch(ExternType),
+}
+
+// Report is what the validator found out about a module.
+#[derive(Debug)]
+pub struct Report {
+    pub exports: Vec<(&'static Spec, Status)>,
+    // memory is whether the module exports its memory as "memory".
+    pub memory: bool,
+    // others are the exports the harness doesn't use.
+    pub others: Vec<String>,
+}
+
+// validate inspects the exports of a compiled module.
+pub fn validate(module: &Module) -> Report {
+    let exports = SPECS
+        .iter()
+        .map(|spec| {
+            let status = match module.get_export(spec.name) {
+                None => Status::Missing,
+                Some(ExternType::Func(ty)) if ty == spec.ty() => Status::Present,
+                Some(ty) => Status::Mismatch(ty),
+            };
+            (spec, status)
+        })
+        .collect();
+    let memory = matches!(module.get_export("memory"), Some(ExternType::Memory(_)));
+    let others = module
+        .exports()
+        .map(|export| export.name().to_string())
+        .filter(|name| name != "memory" && SPECS.iter().all(|spec| spec.name != name))
+        .collect();
+    Report { exports, memory, others }
+}
+
+// inspect compiles the module at `path` and validates its exports.
+pub fn inspect(path: impl AsRef<Path>) -> Result<Report, Error> {
+    let engine = WasmAllocator::engine()?;
+    let module = Module::from_file(&engine, path).map_err(|err| Error::Module(format!("{:?}", err)))?;
+    Ok(validate(&module))
+}
+
+impl Report {
+    // has returns whether the module exports the function named `name` with
+    // the right type.
+    pub fn has(&self, name: &str) -> bool {
+        self.exports.iter().any(|(spec, status)| spec.name == name && matches!(status, Status::Present))
+    }
+
+    // can_walk returns whether the module exports the heap walk.
+    pub fn can_walk(&self) -> bool {
+        self.exports.iter().filter(|(spec, _)| spec.need == Need::Walk).all(|(spec, _)| self.has(spec.name))
+    }
+
+    // check returns the first reason the harness can't use the module, if any.
+    pub fn check(&self) -> Result<(), Error> {
+        for (spec, status) in &self.exports {
+            if let Status::Mismatch(actual) = status {
+                return Err(Error::Signature { name: spec.name.to_string(), expected: spec.ty(), actual: Box::new(actual.clone()) });
+            }
+        }
+        // A module that exports part of the heap walk most likely has a bug.
+        let partial = self.exports.iter().any(|(spec, _)| spec.need == Need::Walk && self.has(spec.name));
+        for (spec, status) in &self.exports {
+            let needed = match spec.need {
+                Need::Required => true,
+                Need::Helper => !self.memory,
+                Need::Walk => partial,
+                Need::Extra => false,
+            };
+            if !needed || !matches!(status, Status::Missing) {
+                continue;
+            }
+            if spec.need == Need::Helper {
+                return Err(Error::Export(format!("the module exports neither {}{} nor its memory", spec.name, signature(&spec.ty()))));
+            }
+            return Err(Error::MissingExport { name: spec.name.to_string(), expected: spec.ty() });
+        }
+        if self.has("free_first") != self.has("free_next") {
+            return Err(Error::Export("the module exports only one of free_first and free_next".to_string()));
+        }
+        Ok(())
+    }
+
+    // capabilities lists what the harness can do with the module.
+    pub fn capabilities(&self) -> Vec<String> {
+        let mut capabilities = vec!["alloc, dealloc and realloc".to_string()];
+        for helper in SPECS.iter().filter(|spec| spec.need == Need::Helper) {
+            if self.has(helper.name) {
+                capabilities.push(format!("{} through the module", helper.name));
+            } else if self.memory {
+                capabilities.push(format!("{} through the memory", helper.name));
+            }
+        }
+        if self.memory {
+            capabilities.push("byte access to the memory".to_string());
+        }
+        if self.can_walk() {
+            capabilities.push("heap walk".to_string());
+            if self.has("free_first") && self.has("free_next") {
+                capabilities.push("free list walk".to_string());
+            }
+            if self.has("heap_coalesces") {
+                capabilities.push("coalescing check".to_string());
+            }
+        }
+        capabilities
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (spec, status) in &self.exports {
+            let (mark, note) = match (status, spec.need) {
+                (Status::Present, _) => ("ok", String::new()),
+                (Status::Mismatch(_), _) => ("WRONG", "has the wrong type".to_string()),
+                (Status::Missing, Need::Required) => ("MISSING", "required".to_string()),
+                (Status::Missing, Need::Helper) if self.memory => ("-", "falls back to the memory".to_string()),
+                (Status::Missing, Need::Helper) => ("MISSING", "required without the memory".to_string()),
+                (Status::Missing, Need::Walk | Need::Extra) => ("-", "optional".to_string()),
+            };
+            let name = format!("{}{}", spec.name, signature(&spec.ty()));
+            writeln!(f, "{}", format!("{:<8} {:<28} {}", mark, name, note).trim_end())?;
+        }
+        match self.memory {
+            true => writeln!(f, "{:<8} memory", "ok")?,
+            false => writeln!(f, "{:<8} {:<28} optional", "-", "memory")?,
+        }
+        if !self.others.is_empty() {
+            writeln!(f, "\nother exports: {}", self.others.join(", "))?;
+        }
+        write!(f, "\ncapabilities:")?;
+        for capability in self.capabilities() {
+            write!(f, "\n  {}", capability)?;
+        }
+        if let Err(err) = self.check() {
+            write!(f, "\n\nthe harness can't use the module: {}", err)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // REQUIRED exports alloc and realloc functions that do nothing.
+    const REQUIRED: &str = r#"
+        (func (export "alloc") (param i32) (result i32) (i32.const 0))
+        (func (export "realloc") (param i32 i32) (result i32) (i32.const 0))
+    "#;
+
+    // validated validates a module given as text.
+    fn validated(wat: &str) -> Report {
+        let engine = WasmAllocator::engine().unwrap();
+        validate(&Module::new(&engine, wat).unwrap())
+    }
+
+    #[test]
+    fn missing_export() {
+        let report = validated(&format!(r#"(module (memory (export "memory") 1) {})"#, REQUIRED));
+        match report.check() {
+            Err(Error::MissingExport { name, .. }) => assert_eq!(name, "dealloc"),
+            other => panic!("expected a missing dealloc, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wrong_signature() {
+        let report = validated(&format!(r#"(module (memory (export "memory") 1) {} (func (export "dealloc") (param i64)))"#, REQUIRED));
+        match report.check() {
+            Err(Error::Signature { name, expected, actual }) => {
+                assert_eq!(name, "dealloc");
+                assert_eq!(expected, spec("dealloc").ty());
+                assert!(matches!(*actual, ExternType::Func(_)));
+            }
+            other => panic!("expected dealloc's signature to mismatch, got {:?}", other),
+        }
+        // An export of the right name that isn't a function is a mismatch too.
+        let report = validated(&format!(r#"(module (memory (export "memory") 1) {} (global (export "dealloc") i32 (i32.const 0)))"#, REQUIRED));
+        assert!(matches!(report.check(), Err(Error::Signature { .. })));
+    }
+
+    #[test]
+    fn helpers_fall_back_to_the_memory() {
+        let dealloc = r#"(func (export "dealloc") (param i32))"#;
+        let with_memory = validated(&format!(r#"(module (memory (export "memory") 1) {} {})"#, REQUIRED, dealloc));
+        assert!(with_memory.check().is_ok());
+        assert!(with_memory.capabilities().contains(&"load through the memory".to_string()));
+        let without_memory = validated(&format!("(module (memory 1) {} {})", REQUIRED, dealloc));
+        assert!(matches!(without_memory.check(), Err(Error::Export(_))));
+    }
+
+    #[test]
+    fn linked_allocator_can_walk() {
+        let report = validated(include_str!("2_linked.wat"));
+        assert!(report.check().is_ok());
+        assert!(report.can_walk());
+    }
+}
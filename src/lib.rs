@@ -0,0 +1,35 @@
+// wasmalloc tests, measures and visualizes dynamic memory allocators written
+// in WebAssembly. WasmAllocator wraps an instance of an allocator module, and
+// the other modules build the tools of the command line on top of it, so that
+// they can be embedded in other tools too.
+
+pub mod allocator;
+pub mod baseline;
+pub mod bench;
+pub mod canary;
+pub mod command;
+pub mod compare;
+pub mod conform;
+pub mod diff;
+pub mod dump;
+pub mod fuel;
+pub mod fuzz;
+pub mod heapcheck;
+pub mod heapmap;
+pub mod import;
+pub mod interface;
+pub mod json;
+pub mod metrics;
+pub mod ops;
+pub mod replay;
+pub mod report;
+pub mod rng;
+pub mod script;
+pub mod session;
+pub mod shadow;
+pub mod svg;
+pub mod timeline;
+pub mod trace;
+pub mod workload;
+
+pub use allocator::{Error, WasmAllocator};
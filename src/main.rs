@@ -1,44 +1,656 @@
+mod options;
+mod repl;
+
+use options::Options;
 use std::env;
-use wasmtime::*;
+use std::error::Error;
+use std::fs;
+use std::process;
+use wasmalloc::canary::Canary;
+use wasmalloc::command::parse_number;
+use wasmalloc::script::Script;
+use wasmalloc::session::Session;
+use wasmalloc::shadow::ShadowHeap;
+use wasmalloc::trace::Recorder;
+use wasmalloc::workload::{self, Workload};
+use wasmalloc::{baseline, bench, compare, conform, diff, dump, fuel, fuzz, heapcheck, heapmap, import, interface, metrics, replay, report, timeline, trace, WasmAllocator};
+
+// new_session instantiates the module for a session. With `--check` the
+// session checks every operation for memory errors, with `--canary` it fills
+// allocated blocks with a pattern and checks they're left intact, and with
+// `--heap-check <every>` it checks the consistency of the heap every few
+// operations, with blocks aligned to `--align` bytes.
+fn new_session(module: &str, options: &Options) -> Result<Session, Box<dyn Error>> {
+    let mut session = Session::new(WasmAllocator::new(module)?);
+    if options.has("--check") {
+        session.shadow = Some(ShadowHeap::new());
+    }
+    if options.has("--canary") {
+        if session.allocator.memory().is_none() {
+            return Err(format!("{} doesn't export its memory, which --canary needs", module).into());
+        }
+        session.canary = Some(Canary::new());
+    }
+    if options.has("--heap-check") {
+        if !session.allocator.can_walk() {
+            return Err(format!("{} doesn't export the heap walk functions, which --heap-check needs", module).into());
+        }
+        session.heap_check = Some(heapcheck::Schedule {
+            every: options.value("--heap-check", 1)?,
+            align: options.value("--align", 4)?,
+        });
+    }
+    Ok(session)
+}
+
+// run_scripts runs each script against a fresh instance of the module and
+// reports the failed expectations. It returns whether all scripts passed.
+fn run_scripts(module: &str, paths: &[String], options: &Options) -> Result<bool, Box<dyn Error>> {
+    let mut passed = true;
+    for path in paths {
+        let script = Script::load(path)?;
+        let failures = script.run(&mut new_session(module, options)?);
+        for failure in &failures {
+            println!("{}:{}: {}", path, failure.line, failure.message);
+        }
+        if failures.is_empty() {
+            println!("{}: ok", path);
+        } else {
+            println!("{}: {} failure(s)", path, failures.len());
+            passed = false;
+        }
+    }
+    Ok(passed)
+}
+
+// baseline_command saves the metrics of workloads run against modules to a
+// baseline file, or checks the current metrics against one:
+//
+//     baseline save <file> <module>... [--workloads a,b] [--time]
+//     baseline check <file> [--threshold <[metric=]percent>]...
+//
+// Workloads default to every preset. `--time` also records timings, measured
+// over `--iterations` runs after `--warmup` runs. Checking prints a table of
+// the changes and exits with a non-zero code if any metric got worse by more
+// than its threshold: 0% by default, and 25% for timings.
+fn baseline_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &["--time"])?;
+    let usage = "Please specify 'save <file> <module>...' or 'check <file>' e.g. 'cargo run -- baseline save baseline.txt src/1_minimal.wat'";
+    let (action, path) = match &options.positional[..] {
+        [action, path, ..] => (action.as_str(), path),
+        _ => {
+            println!("{}", usage);
+            process::exit(2);
+        }
+    };
+    let timing = baseline::Timing {
+        iterations: options.value("--iterations", 5)?,
+        warmup: options.value("--warmup", 1)?,
+    };
+    match action {
+        "save" => {
+            let seed = options.value("--seed", 1)?;
+            let mut workloads = options.list("--workloads");
+            if workloads.is_empty() {
+                workloads = workload::PRESETS.iter().map(|(name, _)| name.to_string()).collect();
+            }
+            let mut entries = Vec::new();
+            for module in &options.positional[2..] {
+                for name in &workloads {
+                    let values = baseline::measure(module, name, seed, options.has("--time").then_some(timing))
+                        .map_err(|err| format!("{} with {}: {}", module, name, err))?;
+                    for (metric, value) in values {
+                        entries.push(baseline::Entry { module: module.clone(), workload: name.clone(), metric, value });
+                    }
+                }
+            }
+            baseline::save(path, seed, &entries)?;
+            println!("Saved {} metrics to {}", entries.len(), path);
+        }
+        "check" => {
+            let thresholds = baseline::Thresholds::parse(&options.list("--threshold"))?;
+            let (seed, entries) = baseline::load(path)?;
+            let mut runs: Vec<(&str, &str)> = Vec::new();
+            for entry in &entries {
+                if !runs.contains(&(entry.module.as_str(), entry.workload.as_str())) {
+                    runs.push((&entry.module, &entry.workload));
+                }
+            }
+            let mut changes = Vec::new();
+            for (module, name) in runs {
+                let recorded: Vec<baseline::Entry> = entries
+                    .iter()
+                    .filter(|entry| entry.module == module && entry.workload == name)
+                    .cloned()
+                    .collect();
+                let timed = recorded.iter().any(|entry| entry.metric.starts_with("time."));
+                let current = baseline::measure(module, name, seed, timed.then_some(timing)).unwrap_or_else(|err| {
+                    println!("{} with {}: {}", module, name, err);
+                    Vec::new()
+                });
+                changes.extend(baseline::compare(&recorded, &current, &thresholds));
+            }
+            baseline::print_changes(&changes);
+            let regressions = changes.iter().filter(|change| change.regressed).count();
+            if regressions > 0 {
+                println!("{} regression(s)", regressions);
+                process::exit(1);
+            }
+        }
+        _ => {
+            println!("{}", usage);
+            process::exit(2);
+        }
+    }
+    Ok(())
+}
+
+// bench_command benchmarks a module with a workload, given as a preset name
+// (generated with `--seed`) or a trace file. `--iterations` and `--warmup`
+// set the number of measured and unmeasured runs, and `--format json` prints
+// the results as JSON instead of a table.
+fn bench_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    if options.positional.len() != 2 {
+        println!("Please specify the allocator and the workload e.g. 'cargo run --release -- bench src/1_minimal.wat stack'");
+        process::exit(2);
+    }
+    let (module, name) = (&options.positional[0], &options.positional[1]);
+    let ops = workload::load(name, options.value("--seed", 1)?)?;
+    let report = match bench::run(module, name, &ops, options.value("--iterations", 5)?, options.value("--warmup", 1)?) {
+        Ok(report) => report,
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    };
+    match options.value("--format", "table".to_string())?.as_str() {
+        "table" => println!("{}", report),
+        "json" => println!("{}", report.to_json()),
+        format => return Err(format!("unknown format '{}', expected table or json", format).into()),
+    }
+    Ok(())
+}
+
+// check_command runs a workload against a module that exports the heap walk
+// functions and checks the consistency of its heap every `--every` operations
+// (by default after each one), with blocks aligned to `--align` bytes. It
+// prints the first problems found with the blocks around them, and exits with
+// a non-zero code if there are any.
+fn check_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    if options.positional.len() != 2 {
+        println!("Please specify the allocator and the workload e.g. 'cargo run -- check src/2_linked.wat small'");
+        process::exit(2);
+    }
+    let (module, name) = (&options.positional[0], &options.positional[1]);
+    let ops = workload::load(name, options.value("--seed", 1)?)?;
+    let report = match heapcheck::run(module, &ops, options.value("--every", 1)?, options.value("--align", 4)?) {
+        Ok(report) => report,
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    };
+    match report.failure {
+        None => println!("{} checks passed", report.checks),
+        Some((index, problems)) => {
+            println!("after op {} ({}):", index, ops[index]);
+            for problem in problems {
+                println!("{}", problem);
+            }
+            process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+// compare_command runs workloads against modules and prints their metrics
+// side by side. Modules default to every allocator in src, and `--workloads`
+// to every preset. Timings are measured over `--iterations` runs after
+// `--warmup` runs, and `--format` picks markdown (the default), csv or json.
+fn compare_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    let mut modules = options.positional.clone();
+    if modules.is_empty() {
+        modules = compare::modules("src")?;
+    }
+    let mut workloads = options.list("--workloads");
+    if workloads.is_empty() {
+        workloads = workload::PRESETS.iter().map(|(name, _)| name.to_string()).collect();
+    }
+    let format: String = options.value("--format", "markdown".to_string())?;
+    if !["markdown", "csv", "json"].contains(&format.as_str()) {
+        return Err(format!("unknown format '{}', expected markdown, csv or json", format).into());
+    }
+    let timing = baseline::Timing {
+        iterations: options.value("--iterations", 3)?,
+        warmup: options.value("--warmup", 1)?,
+    };
+    let rows = compare::run(&modules, &workloads, options.value("--seed", 1)?, timing);
+    match format.as_str() {
+        "markdown" => println!("{}", compare::markdown(&rows)),
+        "csv" => println!("{}", compare::csv(&rows)),
+        _ => println!("{}", compare::to_json(&rows)),
+    }
+    Ok(())
+}
+
+// conform_command runs the conformance suite against a module. Checks can be
+// selected with `--only` and skipped with `--skip`, followed by comma-separated
+// check names, and `--list` lists them.
+fn conform_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &["--list"])?;
+    if options.has("--list") {
+        for check in conform::CHECKS {
+            println!("{:12} {}", check.name, check.description);
+        }
+        return Ok(())
+    }
+    let module = options.positional.first()
+        .ok_or("Please specify the allocator to check e.g. 'cargo run -- conform src/1_minimal.wat'")?;
+    if conform::run(module, &options.list("--only"), &options.list("--skip"))?.failed > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// diff_command compares two modules by applying the same random operations to
+// both. `--seed`, `--runs` and `--ops` control the random sequences.
+fn diff_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    if options.positional.len() != 2 {
+        println!("Please specify the two allocators to compare e.g. 'cargo run -- diff src/1_minimal.wat src/2_linked.wat'");
+        process::exit(2);
+    }
+    let seed = options.value("--seed", 1)?;
+    let runs = options.value("--runs", 10)?;
+    let count = options.value("--ops", 1000)?;
+    if !diff::run(&options.positional[0], &options.positional[1], seed, runs, count)? {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// fuel_command reports the fuel consumed by the calls a workload makes to a
+// module, which is deterministic unlike timings. `--worst <n>` lists the n
+// most expensive calls, and `--format json` prints JSON instead of a table.
+fn fuel_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    if options.positional.len() != 2 {
+        println!("Please specify the allocator and the workload e.g. 'cargo run -- fuel src/1_minimal.wat stack'");
+        process::exit(2);
+    }
+    let (module, name) = (&options.positional[0], &options.positional[1]);
+    let ops = workload::load(name, options.value("--seed", 1)?)?;
+    let report = match fuel::run(module, name, &ops, options.value("--worst", 0)?) {
+        Ok(report) => report,
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    };
+    match options.value("--format", "table".to_string())?.as_str() {
+        "table" => println!("{}", report),
+        "json" => println!("{}", report.to_json()),
+        format => return Err(format!("unknown format '{}', expected table or json", format).into()),
+    }
+    Ok(())
+}
+
+// fuzz_command fuzzes a module with random inputs, or replays saved inputs
+// given after the module path. `--seed`, `--iterations` and `--max-len`
+// control the random inputs, and `--allow-trap <text>` makes traps whose
+// message contains the text expected instead of bugs.
+fn fuzz_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    let (module, inputs) = options.positional.split_first()
+        .ok_or("Please specify the allocator to fuzz e.g. 'cargo run -- fuzz src/1_minimal.wat'")?;
+    let fuzz_options = fuzz::Options {
+        seed: options.value("--seed", 1)?,
+        iterations: options.value("--iterations", 1000)?,
+        max_len: options.value("--max-len", 4096)?,
+        allowed_traps: options.list("--allow-trap"),
+    };
+    if !fuzz::run(module, inputs, &fuzz_options)? {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// metrics_command runs a workload against a module and reports how well it
+// uses memory, sampling the heap every `--every` operations (by default 20
+// times over the workload). `--format json` prints JSON instead of a table.
+fn metrics_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    if options.positional.len() != 2 {
+        println!("Please specify the allocator and the workload e.g. 'cargo run -- metrics src/1_minimal.wat stack'");
+        process::exit(2);
+    }
+    let (module, name) = (&options.positional[0], &options.positional[1]);
+    let ops = workload::load(name, options.value("--seed", 1)?)?;
+    let every = options.value("--every", ops.len().div_ceil(20))?;
+    let report = match metrics::run(module, name, &ops, every) {
+        Ok(report) => report,
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    };
+    match options.value("--format", "table".to_string())?.as_str() {
+        "table" => println!("{}", report),
+        "json" => println!("{}", report.to_json()),
+        format => return Err(format!("unknown format '{}', expected table or json", format).into()),
+    }
+    Ok(())
+}
+
+// report_command writes a self-contained HTML report with charts of workloads
+// run against modules. Modules and workloads default as in compare.
+fn report_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    let (path, modules) = options.positional.split_first()
+        .ok_or("Please specify the report file e.g. 'cargo run --release -- report report.html'")?;
+    let mut modules = modules.to_vec();
+    if modules.is_empty() {
+        modules = compare::modules("src")?;
+    }
+    let mut workloads = options.list("--workloads");
+    if workloads.is_empty() {
+        workloads = workload::PRESETS.iter().map(|(name, _)| name.to_string()).collect();
+    }
+    let timing = baseline::Timing {
+        iterations: options.value("--iterations", 3)?,
+        warmup: options.value("--warmup", 1)?,
+    };
+    let html = report::run(&modules, &workloads, options.value("--seed", 1)?, timing)?;
+    fs::write(path, html).map_err(|err| format!("{}: {}", path, err))?;
+    println!("Wrote {}", path);
+    Ok(())
+}
+
+// record_command records the allocator calls of a session as a trace. The
+// session runs the given scripts, or is interactive if there are none.
+fn record_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &["--check", "--canary"])?;
+    if options.positional.len() < 2 {
+        println!("Please specify the allocator and the trace file e.g. 'cargo run -- record src/1_minimal.wat workload.trace'");
+        process::exit(2);
+    }
+    let (module, path, scripts) = (&options.positional[0], &options.positional[1], &options.positional[2..]);
+    let mut session = new_session(module, &options)?;
+    session.recorder = Some(Recorder::new());
+    if scripts.is_empty() {
+        repl::run(&mut session)?;
+    }
+    for script in scripts {
+        for failure in Script::load(script)?.run(&mut session) {
+            println!("{}:{}: {}", script, failure.line, failure.message);
+        }
+    }
+    let ops = session.recorder.map(|recorder| recorder.ops).unwrap_or_default();
+    trace::save(path, &ops)?;
+    println!("Recorded {} events to {}", ops.len(), path);
+    Ok(())
+}
+
+// replay_command replays a trace or a preset workload, generated with
+// `--seed`, against a module and prints a summary.
+fn replay_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    if options.positional.len() != 2 {
+        println!("Please specify the trace and the allocator e.g. 'cargo run -- replay workload.trace src/1_minimal.wat'");
+        process::exit(2);
+    }
+    let ops = workload::load(&options.positional[0], options.value("--seed", 1)?)?;
+    match replay::replay(WasmAllocator::new(&options.positional[1])?, &ops) {
+        Ok(summary) => println!("{}", summary),
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+// generate_command generates a synthetic workload and saves it as a trace. It
+// starts from the preset given with `--preset`, or the default workload, and
+// `--sizes`, `--lifetime`, `--growth`, `--realloc-percent`, `--ops` and
+// `--max-live` override its parts. `--list` lists the presets.
+fn generate_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &["--list"])?;
+    if options.has("--list") {
+        for (name, description) in workload::PRESETS {
+            println!("{:10} {}", name, description);
+        }
+        return Ok(())
+    }
+    let path = options.positional.first()
+        .ok_or("Please specify the trace file e.g. 'cargo run -- generate workload.trace --preset stack'")?;
+    let preset: String = options.value("--preset", "small".to_string())?;
+    let base = workload::preset(&preset).ok_or_else(|| format!("unknown preset '{}'", preset))?;
+    let workload = Workload {
+        sizes: options.value("--sizes", base.sizes)?,
+        lifetime: options.value("--lifetime", base.lifetime)?,
+        growth: options.value("--growth", base.growth)?,
+        realloc_percent: options.value("--realloc-percent", base.realloc_percent)?,
+        ops: options.value("--ops", base.ops)?,
+        max_live: options.value("--max-live", base.max_live)?,
+    };
+    let ops = workload.generate(options.value("--seed", 1)?);
+    trace::save(path, &ops)?;
+    println!("Generated {} events to {} ({})", ops.len(), path, workload);
+    Ok(())
+}
+
+// heapmap_command renders the memory layout of a module after chosen
+// operations of a workload (`--at`, by default the last one), as text or as an
+// SVG image written to `--svg`. `--width` sets the cells per row and `--cell`
+// the bytes per cell, which by default fits the used memory in 32 rows.
+fn heapmap_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    if options.positional.len() != 2 {
+        println!("Please specify the allocator and the workload e.g. 'cargo run -- heapmap src/2_linked.wat stack --at 100,500'");
+        process::exit(2);
+    }
+    let (module, name) = (&options.positional[0], &options.positional[1]);
+    let ops = workload::load(name, options.value("--seed", 1)?)?;
+    if ops.is_empty() {
+        return Err(format!("workload '{}' has no operations", name).into());
+    }
+    let mut at = Vec::new();
+    for index in options.list("--at") {
+        match index.parse::<usize>() {
+            Ok(index) if index < ops.len() => at.push(index),
+            _ => return Err(format!("invalid operation index '{}', the workload has {} operations", index, ops.len()).into()),
+        }
+    }
+    if at.is_empty() {
+        at.push(ops.len().saturating_sub(1));
+    }
+    let layouts = match heapmap::capture(module, &ops, &at) {
+        Ok(layouts) => layouts,
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    };
+    let width = options.value("--width", 64)?.max(1);
+    // Every map uses the same cell size so that they can be compared.
+    let auto = layouts.iter().map(|layout| heapmap::cell_size(layout, width, 32)).max().unwrap_or(1);
+    let cell = options.value("--cell", auto)?.max(1);
+    match options.value("--svg", String::new())?.as_str() {
+        "" => {
+            let maps: Vec<String> = layouts.iter().map(|layout| heapmap::ascii(layout, width, cell)).collect();
+            println!("{}", maps.join("\n\n"));
+        }
+        path => {
+            fs::write(path, heapmap::svg(&layouts, width, cell, None)).map_err(|err| format!("{}: {}", path, err))?;
+            println!("Wrote {}", path);
+        }
+    }
+    Ok(())
+}
+
+// dump_command prints an annotated hex/ASCII dump of `len` bytes at an address
+// of the memory of a module, after the operations of a workload up to `--at`
+// (by default the last one).
+fn dump_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    if options.positional.len() != 4 {
+        println!("Please specify the allocator, the workload, the address and the length e.g. 'cargo run -- dump src/2_linked.wat small 0 256 --at 10'");
+        process::exit(2);
+    }
+    let (module, name) = (&options.positional[0], &options.positional[1]);
+    let address = parse_number(&options.positional[2])? as u32;
+    let len = match parse_number(&options.positional[3])? {
+        len if len < 0 => return Err(format!("invalid length '{}', it can't be negative", len).into()),
+        len => len as usize,
+    };
+    let ops = workload::load(name, options.value("--seed", 1)?)?;
+    let at = options.value("--at", ops.len().saturating_sub(1))?;
+    if at >= ops.len() {
+        return Err(format!("invalid operation index '{}', the workload has {} operations", at, ops.len()).into());
+    }
+    match dump::replay(module, &ops, at, address, len) {
+        Ok(dump) => println!("{}", dump),
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+// import_command converts an mtrace, ltrace or heaptrack trace of a native
+// program into a trace that can be replayed against the allocators.
+fn import_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    if options.positional.len() != 3 {
+        println!("Please specify the format, the input and the trace file e.g. 'cargo run -- import mtrace mtrace.log workload.trace'");
+        process::exit(2);
+    }
+    let (format, input, path) = (&options.positional[0], &options.positional[1], &options.positional[2]);
+    let format = import::Format::from_name(format)
+        .ok_or_else(|| format!("unknown format '{}', expected mtrace, ltrace or heaptrack", format))?;
+    let text = fs::read_to_string(input).map_err(|err| format!("{}: {}", input, err))?;
+    let ops = import::import(format, &text).map_err(|err| format!("{}: {}", input, err))?;
+    trace::save(path, &ops)?;
+    println!("Imported {} events to {}", ops.len(), path);
+    Ok(())
+}
+
+// interface_command prints which of the functions the harness knows are
+// exported by a module, and what the harness can do with it. It fails if the
+// harness can't use the module.
+fn interface_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    if options.positional.len() != 1 {
+        println!("Please specify the allocator e.g. 'cargo run -- interface src/1_minimal.wat'");
+        process::exit(2);
+    }
+    let report = interface::inspect(&options.positional[0])?;
+    println!("{}", report);
+    if report.check().is_err() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// timeline_command writes an HTML player animating the heap map of a module
+// over a workload, with `--frames` frames evenly spaced over it. `--width` and
+// `--cell` are as in heapmap.
+fn timeline_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, &[])?;
+    if options.positional.len() != 3 {
+        println!("Please specify the allocator, the workload and the output file e.g. 'cargo run -- timeline src/1_minimal.wat stack timeline.html'");
+        process::exit(2);
+    }
+    let (module, name, path) = (&options.positional[0], &options.positional[1], &options.positional[2]);
+    let ops = workload::load(name, options.value("--seed", 1)?)?;
+    if ops.is_empty() {
+        return Err(format!("workload '{}' has no operations", name).into());
+    }
+    let cell = match options.value("--cell", 0)? {
+        0 => None,
+        cell => Some(cell),
+    };
+    let width = options.value("--width", 64)?.max(1);
+    let html = match timeline::run(module, name, &ops, options.value("--frames", 100)?, width, cell) {
+        Ok(html) => html,
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    };
+    fs::write(path, html).map_err(|err| format!("{}: {}", path, err))?;
+    println!("Wrote {}", path);
+    Ok(())
+}
+
+// usage asks for the path of the allocator module.
+fn usage() {
+    println!("Please specify the allocator you want to use e.g. 'cargo run -- src/1_minimal.wat'");
+    println!("To run scripts against it instead: 'cargo run -- src/1_minimal.wat run scripts/*.script'");
+}
 
 // Small program to test and interact with the WebAssembly allocators.
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Box<dyn Error>> {
 
     // Get command line arguments
     let args: Vec<String> = env::args().collect();
 
     // If no arguments ask for .wat path
     if args.len() < 2 {
-        println!("Please specify the allocator you want to use e.g. 'cargo run -- src/1_minimal.wat'");
+        usage();
         return Ok(())
     }
 
-    // Instantiate WebAssembly module from .wat file
-    let mut wasm_store: Store<()> = Store::default();
-    let module = Module::from_file(wasm_store.engine(), &args[1])?;
-    let instance = Instance::new(&mut wasm_store, &module, &[])?;
-
-    // Get callable references to the functions
-    let alloc = instance.get_typed_func::<(i32), i32, _>(&mut wasm_store, "alloc")?;
-    let dealloc = instance.get_typed_func::<(i32), (), _>(&mut wasm_store, "dealloc")?;
-    let realloc = instance.get_typed_func::<(i32, i32), i32, _>(&mut wasm_store, "realloc")?;
-    let store = instance.get_typed_func::<(i32, i32), (), _>(&mut wasm_store, "store")?;
-    let load = instance.get_typed_func::<(i32), i32, _>(&mut wasm_store, "load")?;
-    let size = instance.get_typed_func::<(), i32, _>(&mut wasm_store, "size")?;
-    let grow = instance.get_typed_func::<(i32), i32, _>(&mut wasm_store, "grow")?;
-
-    // Test memory growing
-    // println!("size(): {:?}", size.call(&mut wasm_store, ())?);
-    // println!("grow(1): {:?}", grow.call(&mut wasm_store, 1)?);
-    // println!("size(): {:?}", size.call(&mut wasm_store, ())?);
+    // Run a subcommand if asked to
+    match args[1].as_str() {
+        "baseline" => return baseline_command(&args[2..]),
+        "bench" => return bench_command(&args[2..]),
+        "check" => return check_command(&args[2..]),
+        "compare" => return compare_command(&args[2..]),
+        "conform" => return conform_command(&args[2..]),
+        "diff" => return diff_command(&args[2..]),
+        "dump" => return dump_command(&args[2..]),
+        "fuel" => return fuel_command(&args[2..]),
+        "fuzz" => return fuzz_command(&args[2..]),
+        "generate" => return generate_command(&args[2..]),
+        "heapmap" => return heapmap_command(&args[2..]),
+        "import" => return import_command(&args[2..]),
+        "interface" => return interface_command(&args[2..]),
+        "metrics" => return metrics_command(&args[2..]),
+        "record" => return record_command(&args[2..]),
+        "report" => return report_command(&args[2..]),
+        "replay" => return replay_command(&args[2..]),
+        "timeline" => return timeline_command(&args[2..]),
+        _ => {}
+    }
 
-    // // Test loading and storing
-    // println!("load(0): {:?}", load.call(&mut wasm_store, 0)?);
-    // println!("store(0, 1): {:?}", store.call(&mut wasm_store, (0, 1))?);
-    // println!("load(0): {:?}", load.call(&mut wasm_store, 0)?);
+    // Check for memory errors and corruption if asked to
+    let options = Options::parse(&args[1..], &["--check", "--canary"])?;
+    let module = match options.positional.first() {
+        Some(module) => module,
+        None => {
+            usage();
+            process::exit(2);
+        }
+    };
 
-    // Test a load beyond the first memory page
-    // println!("load(x): {:?}", load.call(&mut wasm_store, 65532)?); // Works with 65532 but not 65533 because there are only 65536 bytes in a page
+    // Run scripts if asked to, exiting with a non-zero code if any fails
+    if options.positional.get(1).map(String::as_str) == Some("run") {
+        if options.positional.len() < 3 {
+            println!("Please specify the script(s) to run");
+            process::exit(2);
+        }
+        if !run_scripts(module, &options.positional[2..], &options)? {
+            process::exit(1);
+        }
+        return Ok(())
+    }
 
-    Ok(())
+    // Start an interactive session
+    repl::run(&mut new_session(module, &options)?)
 }
@@ -0,0 +1,299 @@
+use crate::allocator::{Block, WasmAllocator};
+use crate::json;
+use crate::ops::{event, trapped, Op, Runner, PAGE_SIZE};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+// Heap metrics measure how well an allocator uses memory while it runs a
+// workload. The heap is sampled every few operations: the bytes requested by
+// the live blocks are compared with the size of the memory, and the regions
+// of the memory that no live block covers are treated as free. Those regions
+// also hold block headers and padding, which the host can't tell apart from
+// free memory unless the allocator exports the heap walk functions. When it
+// does, free memory, internal fragmentation and metadata come from its blocks.
+
+// Sample is a measurement of the heap after an operation.
+#[derive(Debug, Clone, Default)]
+pub struct Sample {
+    // index is the index of the operation after which the heap was sampled.
+    pub index: usize,
+    pub blocks: usize,
+    // requested is the total size requested for the live blocks.
+    pub requested: u64,
+    // memory is the size of the memory in bytes.
+    pub memory: u64,
+    // free is the total size of the regions no live block covers.
+    pub free: u64,
+    pub largest_free: u64,
+    // internal is the size granted to the live blocks beyond their requested
+    // size, if known.
+    pub internal: Option<u64>,
+    // metadata is the size of the allocator's bookkeeping, if known.
+    pub metadata: Option<u64>,
+    // walked_blocks is the number of blocks in the heap walk, free ones
+    // included, if known.
+    pub walked_blocks: Option<usize>,
+}
+
+impl Sample {
+    // external_fragmentation returns the share of the free memory that's not
+    // part of the largest free region: zero if all of it could satisfy one
+    // request, and close to one if it's scattered in small pieces.
+    pub fn external_fragmentation(&self) -> f64 {
+        if self.free == 0 {
+            return 0.0;
+        }
+        1.0 - self.largest_free as f64 / self.free as f64
+    }
+}
+
+// sample measures the heap made of the live blocks, given as (address, size).
+fn sample(index: usize, mut blocks: Vec<(u64, u64)>, memory: u64) -> Sample {
+    blocks.sort_unstable();
+    let mut result = Sample { index, blocks: blocks.len(), memory, ..Sample::default() };
+    let mut free = |start: u64, end: u64| {
+        if end > start {
+            result.free += end - start;
+            result.largest_free = result.largest_free.max(end - start);
+        }
+    };
+    // cursor is the end of the covered memory so far.
+    let mut cursor = 0;
+    let mut requested = 0;
+    for (address, size) in blocks {
+        free(cursor, address);
+        cursor = cursor.max(address + size);
+        requested += size;
+    }
+    free(cursor, memory);
+    result.requested = requested;
+    result
+}
+
+// walked corrects a sample with the allocator's own blocks: free memory is the
+// free blocks plus the memory past the last block, internal fragmentation is
+// what the used blocks hold beyond the requested sizes of the live blocks at
+// their addresses, and metadata is the memory before and between blocks.
+fn walked(sample: &mut Sample, walk: &[Block], live: &HashMap<u64, u64>) {
+    let (mut free, mut largest_free, mut internal, mut metadata) = (0, 0, 0, 0);
+    // cursor is the end of the last block so far.
+    let mut cursor = 0;
+    for block in walk {
+        metadata += block.address.saturating_sub(cursor);
+        cursor = block.address + block.size;
+        if block.free {
+            free += block.size;
+            largest_free = largest_free.max(block.size);
+        } else if let Some(&requested) = live.get(&block.address) {
+            internal += block.size.saturating_sub(requested);
+        }
+    }
+    let tail = sample.memory.saturating_sub(cursor);
+    sample.free = free + tail;
+    sample.largest_free = largest_free.max(tail);
+    sample.internal = Some(internal);
+    sample.metadata = Some(metadata);
+    sample.walked_blocks = Some(walk.len());
+}
+
+// Summary sums up the samples of a workload.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub peak_requested: u64,
+    pub peak_memory: u64,
+    // mean_fragmentation and max_fragmentation are the mean and maximum
+    // external fragmentation over the samples.
+    pub mean_fragmentation: f64,
+    pub max_fragmentation: f64,
+    pub peak_internal: Option<u64>,
+    // metadata_per_block is the mean size of the bookkeeping per walked block,
+    // over the samples with walked blocks.
+    pub metadata_per_block: Option<f64>,
+}
+
+impl Summary {
+    // utilization returns the peak requested size relative to the peak size
+    // of the memory.
+    pub fn utilization(&self) -> f64 {
+        if self.peak_memory == 0 {
+            return 0.0;
+        }
+        self.peak_requested as f64 / self.peak_memory as f64
+    }
+}
+
+fn summarize(samples: &[Sample]) -> Summary {
+    let mut summary = Summary::default();
+    for sample in samples {
+        summary.peak_requested = summary.peak_requested.max(sample.requested);
+        summary.peak_memory = summary.peak_memory.max(sample.memory);
+        summary.max_fragmentation = summary.max_fragmentation.max(sample.external_fragmentation());
+        summary.mean_fragmentation += sample.external_fragmentation() / samples.len() as f64;
+        if let Some(internal) = sample.internal {
+            summary.peak_internal = Some(summary.peak_internal.unwrap_or(0).max(internal));
+        }
+    }
+    let per_block: Vec<f64> = samples
+        .iter()
+        .filter(|sample| sample.walked_blocks.is_some_and(|blocks| blocks > 0))
+        .filter_map(|sample| Some(sample.metadata? as f64 / sample.walked_blocks? as f64))
+        .collect();
+    if !per_block.is_empty() {
+        summary.metadata_per_block = Some(per_block.iter().sum::<f64>() / per_block.len() as f64);
+    }
+    summary
+}
+
+// Report holds the heap metrics of a workload run against a module.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub module: String,
+    pub workload: String,
+    pub samples: Vec<Sample>,
+    pub summary: Summary,
+}
+
+// known formats a value that may be unknown, followed by its unit.
+fn known<T: fmt::Display>(value: Option<T>, unit: &str) -> String {
+    value.map_or("unknown".to_string(), |value| format!("{} {}", value, unit))
+}
+
+fn json_number<T: fmt::Display>(value: Option<T>) -> String {
+    value.map_or("null".to_string(), |value| value.to_string())
+}
+
+impl Report {
+    // to_json encodes the report as a JSON object, with sizes in bytes.
+    pub fn to_json(&self) -> String {
+        let samples: Vec<String> = self
+            .samples
+            .iter()
+            .map(|sample| {
+                format!(
+                    "{{\"index\": {}, \"blocks\": {}, \"requested\": {}, \"memory\": {}, \"free\": {}, \"largest_free\": {}, \"external_fragmentation\": {:.4}, \"internal\": {}, \"metadata\": {}, \"walked_blocks\": {}}}",
+                    sample.index,
+                    sample.blocks,
+                    sample.requested,
+                    sample.memory,
+                    sample.free,
+                    sample.largest_free,
+                    sample.external_fragmentation(),
+                    json_number(sample.internal),
+                    json_number(sample.metadata),
+                    json_number(sample.walked_blocks)
+                )
+            })
+            .collect();
+        let summary = &self.summary;
+        format!(
+            "{{\"module\": {}, \"workload\": {}, \"summary\": {{\"peak_requested\": {}, \"peak_memory\": {}, \"utilization\": {:.4}, \"mean_fragmentation\": {:.4}, \"max_fragmentation\": {:.4}, \"peak_internal\": {}, \"metadata_per_block\": {}}}, \"samples\": [{}]}}",
+            json::string(&self.module),
+            json::string(&self.workload),
+            summary.peak_requested,
+            summary.peak_memory,
+            summary.utilization(),
+            summary.mean_fragmentation,
+            summary.max_fragmentation,
+            json_number(summary.peak_internal),
+            json_number(summary.metadata_per_block.map(|size| format!("{:.1}", size))),
+            samples.join(", ")
+        )
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "module:   {}", self.module)?;
+        writeln!(f, "workload: {}", self.workload)?;
+        writeln!(f)?;
+        writeln!(f, "{:>8} {:>7} {:>10} {:>10} {:>10} {:>10} {:>9}", "op", "blocks", "requested", "memory", "free", "largest", "ext frag")?;
+        for sample in &self.samples {
+            writeln!(
+                f,
+                "{:>8} {:>7} {:>10} {:>10} {:>10} {:>10} {:>8.1}%",
+                sample.index,
+                sample.blocks,
+                sample.requested,
+                sample.memory,
+                sample.free,
+                sample.largest_free,
+                sample.external_fragmentation() * 100.0
+            )?;
+        }
+        let summary = &self.summary;
+        writeln!(f)?;
+        writeln!(f, "peak requested:         {} bytes", summary.peak_requested)?;
+        writeln!(f, "peak memory:            {} bytes ({} pages)", summary.peak_memory, summary.peak_memory / PAGE_SIZE)?;
+        writeln!(f, "utilization:            {:.1}%", summary.utilization() * 100.0)?;
+        writeln!(
+            f,
+            "external fragmentation: {:.1}% mean, {:.1}% max",
+            summary.mean_fragmentation * 100.0,
+            summary.max_fragmentation * 100.0
+        )?;
+        writeln!(f, "internal fragmentation: {}", known(summary.peak_internal, "bytes at peak"))?;
+        write!(
+            f,
+            "metadata per block:     {}",
+            known(summary.metadata_per_block.map(|size| format!("{:.1}", size)), "bytes")
+        )
+    }
+}
+
+// run replays the operations against the module at `path`, sampling the heap
+// after every `every` operations and after the last one. It stops at the
+// first trap.
+pub fn run(path: &str, workload: &str, ops: &[Op], every: usize) -> Result<Report, Box<dyn Error>> {
+    let mut runner = Runner::new(WasmAllocator::new(path)?);
+    let mut samples = Vec::new();
+    for (index, op) in ops.iter().enumerate() {
+        runner.step(index, op)?;
+        if (index + 1) % every.max(1) == 0 || index + 1 == ops.len() {
+            let blocks = runner.live_sizes();
+            let memory = runner.allocator.memory_pages()? as u64 * PAGE_SIZE;
+            let walk = runner.allocator.walk().map_err(|err| trapped(&format!("heap walk after {}", event(index, op)), err))?;
+            let mut sample = sample(index, blocks.clone(), memory);
+            if let Some(walk) = walk {
+                walked(&mut sample, &walk, &blocks.into_iter().collect());
+            }
+            samples.push(sample);
+        }
+    }
+    let summary = summarize(&samples);
+    Ok(Report { module: path.to_string(), workload: workload.to_string(), samples, summary })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walked_counts_free_blocks_and_headers() {
+        // Blocks with 4-byte headers: used, free, used, free, then untouched
+        // memory up to 128 bytes.
+        let walk = [
+            Block { address: 4, size: 12, free: false },
+            Block { address: 20, size: 8, free: true },
+            Block { address: 32, size: 16, free: false },
+            Block { address: 52, size: 28, free: true },
+        ];
+        let live = [(4, 10), (32, 16)];
+        let mut sample = sample(0, live.to_vec(), 128);
+        walked(&mut sample, &walk, &live.into_iter().collect());
+        assert_eq!(sample.blocks, 2);
+        assert_eq!(sample.walked_blocks, Some(4));
+        assert_eq!(sample.free, 8 + 28 + 48);
+        assert_eq!(sample.largest_free, 48);
+        assert_eq!(sample.internal, Some(2));
+        assert_eq!(sample.metadata, Some(16));
+        // The headers are divided among all walked blocks, not the live ones.
+        assert_eq!(summarize(&[sample]).metadata_per_block, Some(4.0));
+    }
+
+    #[test]
+    fn summarize_skips_metadata_without_a_walk() {
+        let sample = sample(0, vec![(0, 8)], 64);
+        assert_eq!(summarize(&[sample]).metadata_per_block, None);
+    }
+}
@@ -0,0 +1,259 @@
+use crate::allocator::{Error, WasmAllocator};
+use crate::rng::{Rng, Source};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+
+// PAGE_SIZE is the size of a WebAssembly memory page in bytes.
+pub const PAGE_SIZE: u64 = 65536;
+
+// Op is an operation on a logical block. Blocks are identified by an id
+// instead of an address so that the same sequence of operations can be
+// applied to different allocators, which return different addresses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Alloc { id: usize, size: i32 },
+    Dealloc { id: usize },
+    Realloc { id: usize, size: i32 },
+    // Store and Load access the 32-bit word at `offset` bytes into the block.
+    Store { id: usize, offset: i32, value: i32 },
+    Load { id: usize, offset: i32 },
+}
+
+impl Op {
+    // id returns the id of the block the operation targets.
+    pub fn id(&self) -> usize {
+        match *self {
+            Op::Alloc { id, .. }
+            | Op::Dealloc { id }
+            | Op::Realloc { id, .. }
+            | Op::Store { id, .. }
+            | Op::Load { id, .. } => id,
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Alloc { id, size } => write!(f, "alloc #{} {}", id, size),
+            Op::Dealloc { id } => write!(f, "dealloc #{}", id),
+            Op::Realloc { id, size } => write!(f, "realloc #{} {}", id, size),
+            Op::Store { id, offset, value } => write!(f, "store #{}+{} {}", id, offset, value),
+            Op::Load { id, offset } => write!(f, "load #{}+{}", id, offset),
+        }
+    }
+}
+
+// random_size picks a request size, favoring small blocks like most programs.
+fn random_size(source: &mut impl Source) -> i32 {
+    match source.below(100) {
+        0..=4 => 0,
+        5..=64 => source.range(1, 64),
+        65..=89 => source.range(65, 1024),
+        90..=97 => source.range(1025, 16384),
+        _ => source.range(16385, 131072),
+    }
+}
+
+// Block is a live block while generating operations.
+struct Block {
+    id: usize,
+    size: i32,
+    // written holds the offsets of the words that have been stored to.
+    written: Vec<i32>,
+}
+
+// Generator generates valid sequences of operations from a source of choices.
+// Every operation targets a live block, stores only access whole words inside
+// the block, and loads only read words that were previously stored to (and
+// not cut off by a realloc) since the contents of any other word are up to
+// the allocator.
+#[derive(Default)]
+pub struct Generator {
+    // Zero-size blocks aren't tracked as there's nothing to operate on.
+    live: Vec<Block>,
+    next_id: usize,
+}
+
+impl Generator {
+    // next returns the next operation, or None if the choices made don't lead
+    // to one (e.g. a load from a block that was never stored to).
+    pub fn next(&mut self, source: &mut impl Source) -> Option<Op> {
+        let roll = source.below(100);
+        if self.live.is_empty() || roll < 35 {
+            let id = self.next_id;
+            let size = random_size(source);
+            if size > 0 {
+                self.live.push(Block { id, size, written: Vec::new() });
+            }
+            self.next_id += 1;
+            return Some(Op::Alloc { id, size });
+        }
+        let i = source.below(self.live.len() as u64) as usize;
+        let block = &mut self.live[i];
+        let id = block.id;
+        if roll < 55 {
+            self.live.swap_remove(i);
+            Some(Op::Dealloc { id })
+        } else if roll < 65 {
+            let size = random_size(source).max(1);
+            block.size = size;
+            block.written.retain(|&offset| offset + 4 <= size);
+            Some(Op::Realloc { id, size })
+        } else if roll < 85 || block.written.is_empty() {
+            if block.size < 4 {
+                return None;
+            }
+            let offset = source.below(block.size as u64 / 4) as i32 * 4;
+            block.written.push(offset);
+            Some(Op::Store { id, offset, value: source.next_u64() as i32 })
+        } else {
+            let offset = block.written[source.below(block.written.len() as u64) as usize];
+            Some(Op::Load { id, offset })
+        }
+    }
+}
+
+// random generates `count` random operations.
+pub fn random(rng: &mut Rng, count: usize) -> Vec<Op> {
+    let mut generator = Generator::default();
+    let mut ops = Vec::with_capacity(count);
+    while ops.len() < count {
+        ops.extend(generator.next(rng));
+    }
+    ops
+}
+
+// Effect is the observable result of applying an operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    // The address returned by alloc or realloc.
+    Address(i32),
+    // The value returned by load.
+    Value(i32),
+    // Nothing is returned by dealloc and store.
+    Done,
+    // The block returned by alloc or realloc doesn't fit in the memory, which
+    // is how the allocators signal they couldn't get more memory.
+    OutOfMemory,
+    // The operation targets a block that was never successfully allocated
+    // (because its alloc trapped or ran out of memory) so it wasn't applied.
+    Skipped,
+}
+
+// trapped describes an error of `what`, e.g. "event 3 (alloc #1 8)".
+pub fn trapped(what: &str, err: Error) -> String {
+    format!("{} trapped: {}", what, err.to_string().trim_end())
+}
+
+// event names the operation at `index` of a sequence in messages.
+pub fn event(index: usize, op: &Op) -> String {
+    format!("event {} ({})", index, op)
+}
+
+// Timing is the time and fuel an alloc, dealloc or realloc call took.
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    pub nanos: u64,
+    pub fuel: u64,
+}
+
+// Runner applies operations to an allocator module, keeping track of the
+// address and requested size of every logical block.
+pub struct Runner {
+    pub allocator: WasmAllocator,
+    pub addresses: HashMap<usize, i32>,
+    // timing is the Timing of the alloc, dealloc or realloc call made by the
+    // last operation, if it made one, measured around the call only.
+    pub timing: Option<Timing>,
+    sizes: HashMap<usize, u64>,
+    live_bytes: u64,
+}
+
+impl Runner {
+    pub fn new(allocator: WasmAllocator) -> Runner {
+        Runner { allocator, addresses: HashMap::new(), timing: None, sizes: HashMap::new(), live_bytes: 0 }
+    }
+
+    // timed makes an alloc, dealloc or realloc call, recording its Timing.
+    fn timed<T>(&mut self, call: impl FnOnce(&mut WasmAllocator) -> Result<T, Error>) -> Result<T, Error> {
+        let start = Instant::now();
+        let result = call(&mut self.allocator);
+        let nanos = start.elapsed().as_nanos() as u64;
+        self.timing = Some(Timing { nanos, fuel: self.allocator.fuel_consumed() });
+        result
+    }
+
+    // forget drops the block `id`, which was freed or moved out of the memory.
+    fn forget(&mut self, id: usize) {
+        self.addresses.remove(&id);
+        self.live_bytes -= self.sizes.remove(&id).unwrap_or(0);
+    }
+
+    // place records the address of a block returned by alloc or realloc,
+    // unless it doesn't fit in the memory.
+    fn place(&mut self, id: usize, address: i32, size: i32) -> Result<Effect, Error> {
+        self.forget(id);
+        let size = size as u32 as u64;
+        if address as u32 as u64 + size > self.allocator.memory_pages()? as u64 * PAGE_SIZE {
+            return Ok(Effect::OutOfMemory);
+        }
+        self.addresses.insert(id, address);
+        self.sizes.insert(id, size);
+        self.live_bytes += size;
+        Ok(Effect::Address(address))
+    }
+
+    // live_bytes returns the total requested size of the live blocks.
+    pub fn live_bytes(&self) -> u64 {
+        self.live_bytes
+    }
+
+    // live_sizes returns the (address, requested size) of every live block
+    // that isn't empty, by address.
+    pub fn live_sizes(&self) -> Vec<(u64, u64)> {
+        self.live_ids().into_iter().map(|(_, address, size)| (address, size)).collect()
+    }
+
+    // live_ids is like live_sizes, but also returns the id of every block.
+    pub fn live_ids(&self) -> Vec<(usize, u64, u64)> {
+        let mut live: Vec<_> = self.sizes.iter().filter(|(_, &size)| size > 0).map(|(&id, &size)| (id, self.addresses[&id] as u32 as u64, size)).collect();
+        live.sort_by_key(|&(id, address, _)| (address, id));
+        live
+    }
+
+    // step applies the operation at `index` of a sequence, describing a trap
+    // with the operation.
+    pub fn step(&mut self, index: usize, op: &Op) -> Result<Effect, String> {
+        self.apply(op).map_err(|err| trapped(&event(index, op), err))
+    }
+
+    pub fn apply(&mut self, op: &Op) -> Result<Effect, Error> {
+        self.timing = None;
+        let address = self.addresses.get(&op.id()).copied();
+        match (*op, address) {
+            (Op::Alloc { id, size }, _) => {
+                let address = self.timed(|allocator| allocator.alloc(size))?;
+                self.place(id, address, size)
+            }
+            (_, None) => Ok(Effect::Skipped),
+            (Op::Dealloc { id }, Some(address)) => {
+                self.timed(|allocator| allocator.dealloc(address))?;
+                self.forget(id);
+                Ok(Effect::Done)
+            }
+            (Op::Realloc { id, size }, Some(address)) => {
+                let new = self.timed(|allocator| allocator.realloc(address, size))?;
+                self.place(id, new, size)
+            }
+            (Op::Store { offset, value, .. }, Some(address)) => {
+                self.allocator.store(address.wrapping_add(offset), value)?;
+                Ok(Effect::Done)
+            }
+            (Op::Load { offset, .. }, Some(address)) => {
+                Ok(Effect::Value(self.allocator.load(address.wrapping_add(offset))?))
+            }
+        }
+    }
+}
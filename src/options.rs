@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// Options holds the arguments of a subcommand: positional arguments and
+// `--name value` flags. Flags listed as switches when parsing take no value.
+pub struct Options {
+    pub positional: Vec<String>,
+    flags: HashMap<String, Vec<String>>,
+}
+
+impl Options {
+    pub fn parse(args: &[String], switches: &[&str]) -> Result<Options, String> {
+        let mut positional = Vec::new();
+        let mut flags: HashMap<String, Vec<String>> = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg.clone());
+            } else if switches.contains(&arg.as_str()) {
+                flags.entry(arg.clone()).or_default();
+            } else {
+                let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+                flags.entry(arg.clone()).or_default().push(value.clone());
+            }
+        }
+        Ok(Options { positional, flags })
+    }
+
+    // has returns whether the flag was given.
+    pub fn has(&self, name: &str) -> bool {
+        self.flags.contains_key(name)
+    }
+
+    // value parses the last value given for the flag, or returns the default.
+    pub fn value<T: FromStr>(&self, name: &str, default: T) -> Result<T, String> {
+        match self.flags.get(name).and_then(|values| values.last()) {
+            Some(value) => value.parse().map_err(|_| format!("invalid value '{}' for {}", value, name)),
+            None => Ok(default),
+        }
+    }
+
+    // list returns every comma-separated value given for the flag, which can
+    // also be repeated, e.g. `--skip a,b --skip c`.
+    pub fn list(&self, name: &str) -> Vec<String> {
+        self.flags
+            .get(name)
+            .into_iter()
+            .flatten()
+            .flat_map(|value| value.split(','))
+            .map(String::from)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str, switches: &[&str]) -> Result<Options, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        Options::parse(&args, switches)
+    }
+
+    #[test]
+    fn switches_take_no_value() {
+        let options = parse("a.wat --check run b.script", &["--check"]).unwrap();
+        assert!(options.has("--check"));
+        assert_eq!(options.positional, ["a.wat", "run", "b.script"]);
+    }
+
+    #[test]
+    fn flags_take_the_next_argument() {
+        let options = parse("--check a.wat run", &[]).unwrap();
+        assert_eq!(options.value("--check", String::new()).unwrap(), "a.wat");
+        assert_eq!(options.positional, ["run"]);
+        assert_eq!(parse("a.wat --seed", &[]).err().unwrap(), "--seed needs a value");
+    }
+
+    #[test]
+    fn values() {
+        let options = parse("--seed 1 --seed 7 --every x", &[]).unwrap();
+        assert_eq!(options.value("--seed", 0u64).unwrap(), 7);
+        assert_eq!(options.value("--runs", 100u64).unwrap(), 100);
+        assert_eq!(options.value("--every", 1usize).err().unwrap(), "invalid value 'x' for --every");
+        assert!(!options.has("--runs"));
+    }
+
+    #[test]
+    fn lists() {
+        let options = parse("--skip a,b --skip c", &[]).unwrap();
+        assert_eq!(options.list("--skip"), ["a", "b", "c"]);
+        assert!(options.list("--only").is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+use rustyline::Editor;
+use rustyline::error::ReadlineError;
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+use wasmalloc::command::parse_statement;
+use wasmalloc::session::Session;
+
+const HELP: &str = "\
+Commands:
+  alloc <size>             allocate a block, e.g. `alloc 24`
+  free <addr>              deallocate a block (alias: dealloc)
+  realloc <addr> <size>    reallocate a block, e.g. `realloc $a 100`
+  store <addr> <value>     store a 32-bit value
+  load <addr>              load a 32-bit value
+  size                     print the memory size in pages
+  grow <pages>             grow the memory and print the previous size
+  read <addr> <len>        print the bytes at an address, read from the memory
+  write <addr> <byte>...   write bytes into the memory, e.g. `write $a 0x68 0x69`
+  read8|16|32 <addr>       read a little-endian integer from the memory
+  write8|16|32 <addr> <v>  write a little-endian integer into the memory
+  handles                  list the named handles
+  blocks                   list the allocator's blocks, if it exports a heap walk
+  dump <addr> <len>        print a hex/ASCII dump annotated with the known blocks
+  history                  list the previously entered lines
+  help                     print this message
+  quit                     exit (alias: exit, Ctrl-D)
+
+Results of alloc and realloc are bound to handles named $1, $2, etc.
+Use `$name = alloc <size>` to pick a name. Realloc of a handle rebinds it.
+Arguments can be numbers (e.g. 16, 0x10, -1) or handles (e.g. $a, $a+4).";
+
+// history_path returns the file where the REPL history is persisted.
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".wasmalloc_history"))
+}
+
+// run reads lines from the terminal and executes them until the user quits.
+// Errors and traps are printed and don't end the session.
+pub fn run(session: &mut Session) -> Result<(), Box<dyn Error>> {
+    let mut editor = Editor::<()>::new()?;
+    let history = history_path();
+    if let Some(path) = &history {
+        // The history file doesn't exist the first time around.
+        let _ = editor.load_history(path);
+    }
+    println!("Type 'help' for a list of commands.");
+
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        editor.add_history_entry(line);
+
+        match line {
+            "quit" | "exit" => break,
+            "help" => println!("{}", HELP),
+            "handles" => {
+                let mut handles: Vec<_> = session.handles.iter().collect();
+                handles.sort();
+                for (name, address) in handles {
+                    println!("${} = {}", name, address);
+                }
+            }
+            "blocks" => match session.allocator.walk() {
+                Ok(Some(blocks)) => {
+                    for block in blocks {
+                        println!("{:#010x} {:>10} {}", block.address, block.size, if block.free { "free" } else { "used" });
+                    }
+                }
+                Ok(None) => println!("the module doesn't export the heap walk functions"),
+                Err(err) => println!("{}", err),
+            },
+            "history" => {
+                for (i, entry) in editor.history().iter().enumerate() {
+                    println!("{:5}  {}", i + 1, entry);
+                }
+            }
+            _ => match parse_statement(line) {
+                Ok(statement) => {
+                    match session.execute(&statement) {
+                        Ok(outcome) => println!("{}", outcome),
+                        Err(err) => println!("{}", err),
+                    }
+                    for violation in session.take_violations() {
+                        println!("memory error: {}", violation);
+                    }
+                }
+                Err(err) => println!("error: {}", err),
+            },
+        }
+    }
+
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use crate::allocator::WasmAllocator;
+use crate::ops::{Effect, Op, Runner, PAGE_SIZE};
+use std::error::Error;
+use std::fmt;
+
+// Summary describes a replayed trace.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub allocs: usize,
+    pub frees: usize,
+    pub reallocs: usize,
+    // out_of_memory counts the requests that returned unusable blocks.
+    pub out_of_memory: usize,
+    // peak_live_bytes is the largest total size of the live blocks.
+    pub peak_live_bytes: u64,
+    // pages is the size of the memory at the end of the replay.
+    pub pages: u32,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "allocs:          {}", self.allocs)?;
+        writeln!(f, "frees:           {}", self.frees)?;
+        writeln!(f, "reallocs:        {}", self.reallocs)?;
+        writeln!(f, "out of memory:   {}", self.out_of_memory)?;
+        writeln!(f, "peak live bytes: {}", self.peak_live_bytes)?;
+        write!(f, "memory size:     {} pages ({} bytes)", self.pages, self.pages as u64 * PAGE_SIZE)
+    }
+}
+
+// replay applies the trace to the allocator, mapping the logical block ids to
+// the addresses it returns. It stops at the first trap.
+pub fn replay(allocator: WasmAllocator, ops: &[Op]) -> Result<Summary, Box<dyn Error>> {
+    let mut runner = Runner::new(allocator);
+    let mut summary = Summary::default();
+    for (index, op) in ops.iter().enumerate() {
+        let effect = runner.step(index, op)?;
+        if effect == Effect::Skipped {
+            continue;
+        }
+        match *op {
+            Op::Alloc { .. } => summary.allocs += 1,
+            Op::Dealloc { .. } => summary.frees += 1,
+            Op::Realloc { .. } => summary.reallocs += 1,
+            Op::Store { .. } | Op::Load { .. } => {}
+        }
+        if effect == Effect::OutOfMemory {
+            summary.out_of_memory += 1;
+        }
+        summary.peak_live_bytes = summary.peak_live_bytes.max(runner.live_bytes());
+    }
+    summary.pages = runner.allocator.memory_pages()?;
+    Ok(summary)
+}
@@ -0,0 +1,160 @@
+use crate::baseline::Timing;
+use crate::bench::{self, format_duration, Histogram};
+use crate::metrics::{self, Sample};
+use crate::ops::PAGE_SIZE;
+use crate::svg::{escape, Chart, Series};
+use crate::{fuel, workload};
+use std::error::Error;
+use std::path::Path;
+
+// The HTML report shows how allocators behave over workloads with charts of
+// the memory size, the live bytes and the external fragmentation over time,
+// and of the latency distribution of their calls. Everything is inline, so the
+// report is a single file that can be attached to a review.
+
+// SAMPLES is the number of times the heap is sampled over a workload.
+const SAMPLES: usize = 200;
+
+// PERCENTILES are the points of the latency distribution that are charted.
+const PERCENTILES: [f64; 11] = [1.0, 10.0, 25.0, 50.0, 75.0, 90.0, 95.0, 99.0, 99.5, 99.9, 100.0];
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin: 1em 0; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: right; }
+th:first-child, td:first-child { text-align: left; }
+.charts { display: flex; flex-wrap: wrap; gap: 1em; }
+.error { color: #b00; }";
+
+// format_bytes formats a size in bytes with a readable unit.
+pub fn format_bytes(bytes: f64) -> String {
+    if bytes >= 1024.0 * 1024.0 {
+        format!("{:.1} MiB", bytes / (1024.0 * 1024.0))
+    } else if bytes >= 1024.0 {
+        format!("{:.1} KiB", bytes / 1024.0)
+    } else {
+        format!("{:.0} B", bytes)
+    }
+}
+
+// Run holds the results of a workload run against a module.
+struct Run {
+    module: String,
+    result: Result<(metrics::Report, Histogram, fuel::Report), String>,
+}
+
+fn measure(module: &str, name: &str, ops: &[crate::ops::Op], timing: Timing) -> Result<(metrics::Report, Histogram, fuel::Report), Box<dyn Error>> {
+    let metrics = metrics::run(module, name, ops, ops.len().div_ceil(SAMPLES))?;
+    let latencies = bench::run(module, name, ops, timing.iterations, timing.warmup)?.total();
+    let fuel = fuel::run(module, name, ops, 0)?;
+    Ok((metrics, latencies, fuel))
+}
+
+// module_name returns the file name of a module path.
+fn module_name(module: &str) -> String {
+    Path::new(module).file_name().map_or(module.to_string(), |name| name.to_string_lossy().to_string())
+}
+
+// sample_series returns a series per successful run, charting a value of the
+// heap samples.
+fn sample_series(runs: &[Run], value: impl Fn(&Sample) -> f64) -> Vec<Series> {
+    runs.iter()
+        .filter_map(|run| {
+            let (metrics, _, _) = run.result.as_ref().ok()?;
+            let points = metrics.samples.iter().map(|sample| (sample.index as f64, value(sample))).collect();
+            Some(Series { name: module_name(&run.module), points })
+        })
+        .collect()
+}
+
+// section returns the HTML of the results of a workload.
+fn section(name: &str, runs: &[Run]) -> String {
+    let description = workload::PRESETS
+        .iter()
+        .find(|(preset, _)| *preset == name)
+        .map_or("trace file".to_string(), |(_, description)| description.to_string());
+    let mut html = format!("<h2>{}</h2>\n<p>{}</p>\n", escape(name), escape(&description));
+
+    html += "<table>\n<tr><th>Implementation</th><th>Fuel/op</th><th>p50</th><th>p99</th><th>Peak pages</th><th>Utilization</th><th>Fragmentation</th></tr>\n";
+    for run in runs {
+        html += &format!("<tr><td>{}</td>", escape(&module_name(&run.module)));
+        match &run.result {
+            Ok((metrics, latencies, fuel)) => {
+                let total = fuel.total();
+                html += &format!(
+                    "<td>{:.1}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.1}%</td><td>{:.1}%</td></tr>\n",
+                    total.sum as f64 / total.count.max(1) as f64,
+                    format_duration(latencies.percentile(50.0)),
+                    format_duration(latencies.percentile(99.0)),
+                    metrics.summary.peak_memory / PAGE_SIZE,
+                    metrics.summary.utilization() * 100.0,
+                    metrics.summary.mean_fragmentation * 100.0
+                );
+            }
+            Err(err) => html += &format!("<td colspan=\"6\" class=\"error\">{}</td></tr>\n", escape(err)),
+        }
+    }
+    html += "</table>\n<div class=\"charts\">\n";
+
+    let operation = |x: f64| format!("{:.0}", x);
+    let percent = |y: f64| format!("{:.0}%", y * 100.0);
+    let over_time = |title, format_y| Chart { title, x_label: "operation", format_x: &operation, format_y, log: false };
+    let charts = [
+        (over_time("Memory size", &format_bytes), sample_series(runs, |sample| sample.memory as f64)),
+        (over_time("Live bytes", &format_bytes), sample_series(runs, |sample| sample.requested as f64)),
+        (over_time("External fragmentation", &percent), sample_series(runs, |sample| sample.external_fragmentation())),
+    ];
+    for (chart, series) in charts {
+        html += &chart.draw(&series);
+        html += "\n";
+    }
+
+    // The latency distribution is charted at evenly spaced positions, one per
+    // percentile, so that the tail is as readable as the median.
+    let latencies: Vec<Series> = runs
+        .iter()
+        .filter_map(|run| {
+            let (_, latencies, _) = run.result.as_ref().ok()?;
+            let points = PERCENTILES
+                .iter()
+                .enumerate()
+                .map(|(i, &percentile)| (i as f64, latencies.percentile(percentile) as f64))
+                .collect();
+            Some(Series { name: module_name(&run.module), points })
+        })
+        .collect();
+    let position = |x: f64| {
+        let percentile = PERCENTILES[(x.round() as usize).min(PERCENTILES.len() - 1)];
+        if percentile == 100.0 { "max".to_string() } else { format!("p{}", percentile) }
+    };
+    let duration = |y: f64| format_duration(y as u64);
+    let chart = Chart {
+        title: "Latency distribution (all calls)",
+        x_label: "percentile",
+        format_x: &position,
+        format_y: &duration,
+        log: true,
+    };
+    html += &chart.draw(&latencies);
+    html + "\n</div>\n"
+}
+
+// run measures the workloads against the modules and returns the report.
+pub fn run(modules: &[String], workloads: &[String], seed: u64, timing: Timing) -> Result<String, Box<dyn Error>> {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>wasmalloc report</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>wasmalloc report</h1>\n<p>Seed {}, latencies over {} iterations after {} warmup. Latencies include fuel metering.</p>\n",
+        STYLE, seed, timing.iterations, timing.warmup
+    );
+    for name in workloads {
+        let ops = workload::load(name, seed)?;
+        let runs: Vec<Run> = modules
+            .iter()
+            .map(|module| Run {
+                module: module.clone(),
+                result: measure(module, name, &ops, timing).map_err(|err| err.to_string().lines().next().unwrap_or("").to_string()),
+            })
+            .collect();
+        html += &section(name, &runs);
+    }
+    Ok(html + "</body>\n</html>\n")
+}
@@ -0,0 +1,44 @@
+// Source is a source of choices for generating operations. It's implemented by
+// Rng for random testing and by fuzz::Input to decode fuzzer inputs.
+pub trait Source {
+    fn next_u64(&mut self) -> u64;
+
+    // below returns a number in [0, n). n must be non-zero.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    // range returns a number in [low, high].
+    fn range(&mut self, low: i32, high: i32) -> i32 {
+        low + self.below((high - low) as u64 + 1) as i32
+    }
+
+    // fraction returns a number in [0, 1).
+    fn fraction(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// Rng is a small, deterministic pseudo-random number generator (SplitMix64).
+// Runs that use the same seed produce the same sequence of operations, which
+// is all we need to make randomized testing reproducible.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+}
+
+impl Source for Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
@@ -0,0 +1,240 @@
+use crate::allocator;
+use crate::command::{parse_number, parse_operand, parse_statement, Operand, Statement};
+use crate::session::{ExecError, Outcome, Session};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A script is a plain-text file with one statement per line, in the same
+// syntax as the interactive session. Statements can be followed by
+// expectations about their outcome:
+//
+//     # Blank lines and lines starting with '#' are ignored.
+//     $a = alloc 24
+//     store $a 42
+//     load $a
+//     expect 42
+//     load 65533
+//     expect-trap out of bounds
+//     read 0xFFFFFFFE 4
+//     expect-error out of bounds
+//     write $a 0x68 0x69
+//     read $a 2
+//     expect-bytes 0x68 0x69
+//
+// `expect` takes a number or a handle that the previous statement must
+// return, or `ok` to only check that it didn't trap. `expect-trap` checks
+// that the module trapped while executing the previous statement, optionally
+// with a message containing the given text. `expect-error` is like it, but
+// also accepts errors of the host, e.g. a read beyond the end of the memory.
+// `expect-bytes` checks the bytes returned by `read`. A statement that traps
+// or fails without a following `expect-trap` or `expect-error` is a failure.
+
+// Expectation is what a script expects from the statement preceding it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expectation {
+    // The statement returns this value (or address).
+    Value(Operand),
+    // The statement doesn't trap.
+    Ok,
+    // The statement traps, optionally with a message containing the text.
+    Trap(Option<String>),
+    // The statement traps or the host fails to execute it, optionally with a
+    // message containing the text.
+    Error(Option<String>),
+    // The statement returns these bytes.
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Line {
+    Statement(Statement),
+    Expect(Expectation),
+}
+
+// Script is a parsed script file.
+pub struct Script {
+    // lines holds the parsed lines with their 1-based line numbers.
+    lines: Vec<(usize, Line)>,
+}
+
+// Failure is a failed expectation, or an error, at a specific script line.
+#[derive(Debug)]
+pub struct Failure {
+    pub line: usize,
+    pub message: String,
+}
+
+// ParseError is a syntax error at a specific script line.
+#[derive(Debug)]
+pub struct ParseError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+fn parse_expectation(directive: &str, rest: &str) -> Result<Expectation, String> {
+    match directive {
+        "expect" => match rest {
+            "" => Err("'expect' needs a value, or 'ok'".to_string()),
+            "ok" => Ok(Expectation::Ok),
+            _ => Ok(Expectation::Value(parse_operand(rest)?)),
+        },
+        "expect-bytes" => {
+            let mut bytes = Vec::new();
+            for token in rest.split_whitespace() {
+                match parse_number(token)? {
+                    byte @ -128..=255 => bytes.push(byte as u8),
+                    _ => return Err(format!("'{}' doesn't fit in a byte", token)),
+                }
+            }
+            Ok(Expectation::Bytes(bytes))
+        }
+        "expect-error" => Ok(Expectation::Error(Some(rest.to_string()).filter(|rest| !rest.is_empty()))),
+        _ => Ok(Expectation::Trap(Some(rest.to_string()).filter(|rest| !rest.is_empty()))),
+    }
+}
+
+impl Script {
+    // parse parses the text of a script. The path is only used for messages.
+    pub fn parse(path: impl AsRef<Path>, text: &str) -> Result<Script, ParseError> {
+        let path = path.as_ref().to_path_buf();
+        let mut lines = Vec::new();
+        for (i, text) in text.lines().enumerate() {
+            let text = text.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+            let (first, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+            let parsed = match first {
+                "expect" | "expect-trap" | "expect-error" | "expect-bytes" => {
+                    if !matches!(lines.last(), Some((_, Line::Statement(_)))) {
+                        Err(format!("'{}' must follow a statement", first))
+                    } else {
+                        parse_expectation(first, rest.trim()).map(Line::Expect)
+                    }
+                }
+                _ => parse_statement(text).map(Line::Statement),
+            };
+            match parsed {
+                Ok(line) => lines.push((i + 1, line)),
+                Err(message) => return Err(ParseError { path, line: i + 1, message }),
+            }
+        }
+        Ok(Script { lines })
+    }
+
+    // load reads and parses a script file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Script, Box<dyn Error>> {
+        let text = fs::read_to_string(&path)
+            .map_err(|err| format!("{}: {}", path.as_ref().display(), err))?;
+        Ok(Script::parse(path, &text)?)
+    }
+
+    // run executes the script and returns the failed expectations. Execution
+    // continues after a wrong result so that all of them are reported at once,
+    // but it stops at the first unexpected trap or error since the statements
+    // that follow usually depend on the one that failed.
+    pub fn run(&self, session: &mut Session) -> Vec<Failure> {
+        let mut failures = Vec::new();
+        let mut i = 0;
+        while i < self.lines.len() {
+            let (line, statement) = match &self.lines[i] {
+                (line, Line::Statement(statement)) => (*line, statement),
+                // Expectations are consumed together with their statement.
+                _ => unreachable!(),
+            };
+            let result = session.execute(statement);
+            for violation in session.take_violations() {
+                failures.push(Failure { line, message: format!("memory error: {}", violation) });
+            }
+            i += 1;
+
+            let expectation = match self.lines.get(i) {
+                Some((_, Line::Expect(expectation))) => {
+                    i += 1;
+                    expectation
+                }
+                _ => {
+                    // Without an expectation, only traps and errors fail.
+                    if let Err(err) = result {
+                        failures.push(Failure { line, message: format!("unexpected {}", err) });
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let line = self.lines[i - 1].0;
+            let stop = matches!(
+                (expectation, &result),
+                (_, Err(ExecError::Operand(_))) | (Expectation::Value(_) | Expectation::Ok, Err(_))
+            );
+            if let Err(message) = check(session, expectation, result) {
+                failures.push(Failure { line, message });
+            }
+            if stop {
+                break;
+            }
+        }
+        failures
+    }
+}
+
+// containing checks that an expected error has a message containing the text,
+// if any.
+fn containing(what: &str, text: &Option<String>, err: allocator::Error) -> Result<(), String> {
+    match text {
+        Some(text) if !err.to_string().contains(text.as_str()) => Err(format!("expected {} containing '{}', got: {}", what, text, err)),
+        _ => Ok(()),
+    }
+}
+
+// check compares the result of a statement against an expectation.
+fn check(
+    session: &Session,
+    expectation: &Expectation,
+    result: Result<Outcome, ExecError>,
+) -> Result<(), String> {
+    match (expectation, result) {
+        (_, Err(ExecError::Operand(msg))) => Err(msg),
+        (Expectation::Trap(text), Err(ExecError::Trap(err))) => containing("a trap", text, err),
+        (Expectation::Error(text), Err(ExecError::Trap(err) | ExecError::Host(err))) => containing("an error", text, err),
+        (Expectation::Trap(_), Ok(outcome)) => Err(format!("expected a trap, got {}", outcome)),
+        (Expectation::Error(_), Ok(outcome)) => Err(format!("expected an error, got {}", outcome)),
+        (_, Err(err)) => Err(format!("unexpected {}", err)),
+        (Expectation::Ok, Ok(_)) => Ok(()),
+        (Expectation::Value(operand), Ok(outcome)) => {
+            let expected = operand.resolve(&session.handles)?;
+            let actual = match outcome {
+                Outcome::Bound(_, address) => address,
+                Outcome::Value(value) => value,
+                Outcome::Done => return Err(format!("expected {}, got nothing", operand)),
+                Outcome::Bytes(..) => return Err(format!("expected {}, got bytes", operand)),
+                Outcome::Dump(_) => return Err(format!("expected {}, got a dump", operand)),
+            };
+            if actual == expected {
+                Ok(())
+            } else {
+                Err(format!("expected {}, got {}", operand, actual))
+            }
+        }
+        (Expectation::Bytes(expected), Ok(Outcome::Bytes(_, actual))) => {
+            let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ");
+            if actual == *expected {
+                Ok(())
+            } else {
+                Err(format!("expected bytes {}, got {}", hex(expected), hex(&actual)))
+            }
+        }
+        (Expectation::Bytes(_), Ok(outcome)) => Err(format!("expected bytes, got {}", outcome)),
+    }
+}
@@ -0,0 +1,326 @@
+use crate::allocator::{Error, WasmAllocator};
+use crate::canary::Canary;
+use crate::command::{Command, Operand, Statement};
+use crate::dump;
+use crate::heapcheck::{self, Schedule};
+use crate::shadow::{ShadowHeap, Violation, ViolationKind};
+use crate::trace::Recorder;
+use std::collections::HashMap;
+use std::fmt;
+
+// Outcome is the observable result of executing a statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    // An address returned by alloc or realloc, bound to the named handle.
+    Bound(String, i32),
+    // A value returned by load, size or grow.
+    Value(i32),
+    // Nothing is returned by dealloc and store.
+    Done,
+    // The bytes returned by read, with the address they were read at.
+    Bytes(u32, Vec<u8>),
+    // The annotated dump returned by dump.
+    Dump(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Bound(name, address) => write!(f, "${} = {}", name, address),
+            Outcome::Value(value) => write!(f, "{}", value),
+            Outcome::Done => write!(f, "ok"),
+            Outcome::Bytes(address, bytes) => write!(f, "{}", dump::hex(*address, bytes)),
+            Outcome::Dump(dump) => write!(f, "{}", dump),
+        }
+    }
+}
+
+// ExecError is why a statement couldn't be executed.
+#[derive(Debug)]
+pub enum ExecError {
+    // An operand couldn't be resolved, e.g. an unknown handle.
+    Operand(String),
+    // The module trapped while executing the command.
+    Trap(Error),
+    // The host couldn't execute the command, e.g. an access beyond the end
+    // of the memory through the memory instead of the module.
+    Host(Error),
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::Operand(msg) => write!(f, "{}", msg),
+            ExecError::Trap(err) | ExecError::Host(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<Error> for ExecError {
+    fn from(err: Error) -> ExecError {
+        match err {
+            Error::Trap(_) => ExecError::Trap(err),
+            _ => ExecError::Host(err),
+        }
+    }
+}
+
+// Session executes statements against an allocator module and keeps track of
+// the named handles bound to the addresses it returned.
+pub struct Session {
+    pub allocator: WasmAllocator,
+    pub handles: HashMap<String, i32>,
+    // next_handle is the number used to name the next automatic handle.
+    next_handle: u32,
+    // shadow, when set, checks every operation for memory errors.
+    pub shadow: Option<ShadowHeap>,
+    // canary, when set, fills allocated blocks with a pattern and checks
+    // they're left intact. It needs the module to export its memory.
+    pub canary: Option<Canary>,
+    // recorder, when set, records the allocator calls as a trace.
+    pub recorder: Option<Recorder>,
+    // heap_check, when set, checks the consistency of the allocator's heap
+    // every few operations. It needs the module to export the heap walk.
+    pub heap_check: Option<Schedule>,
+    // index is the index of the next operation, used in violation reports.
+    index: usize,
+    violations: Vec<Violation>,
+}
+
+impl Session {
+    pub fn new(allocator: WasmAllocator) -> Session {
+        Session {
+            allocator,
+            handles: HashMap::new(),
+            next_handle: 1,
+            shadow: None,
+            canary: None,
+            recorder: None,
+            heap_check: None,
+            index: 0,
+            violations: Vec::new(),
+        }
+    }
+
+    // take_violations returns the memory errors found since the last call.
+    pub fn take_violations(&mut self) -> Vec<Violation> {
+        std::mem::take(&mut self.violations)
+    }
+
+    // track records a successful operation in the shadow heap, if enabled.
+    fn track(&mut self, record: impl FnOnce(&mut ShadowHeap, usize) -> Vec<Violation>) {
+        if let Some(shadow) = &mut self.shadow {
+            self.violations.extend(record(shadow, self.index));
+        }
+    }
+
+    fn resolve(&self, operand: &Operand) -> Result<i32, ExecError> {
+        operand.resolve(&self.handles).map_err(ExecError::Operand)
+    }
+
+    // resolve_len resolves an operand that's a number of bytes, which can't be
+    // negative.
+    fn resolve_len(&self, operand: &Operand) -> Result<usize, ExecError> {
+        match self.resolve(operand)? {
+            len if len < 0 => Err(ExecError::Operand(format!("invalid length {}, it can't be negative", len))),
+            len => Ok(len as usize),
+        }
+    }
+
+    // bind_name picks the handle name for the result of an alloc or realloc.
+    // An explicit `$name =` wins. Otherwise a realloc of a plain handle rebinds
+    // that handle, and anything else gets the next automatic name ($1, $2...).
+    fn bind_name(&mut self, statement: &Statement) -> String {
+        if let Some(name) = &statement.bind {
+            return name.clone();
+        }
+        if let Command::Realloc(Operand::Handle(name, 0), _) = &statement.command {
+            return name.clone();
+        }
+        let name = self.next_handle.to_string();
+        self.next_handle += 1;
+        name
+    }
+
+    // watch runs a canary step on the memory, if the canary is enabled.
+    fn watch(&mut self, step: impl FnOnce(&mut Canary, &mut [u8], usize) -> Vec<Violation>) {
+        if let (Some(canary), Some(memory)) = (&mut self.canary, self.allocator.memory_mut()) {
+            self.violations.extend(step(canary, memory, self.index));
+        }
+    }
+
+    // check_heap checks the consistency of the heap if it's due, reporting
+    // each problem as a violation. With the shadow heap, the live blocks are
+    // checked against the allocator's blocks too.
+    fn check_heap(&mut self) -> Result<(), Error> {
+        let schedule = match self.heap_check {
+            Some(schedule) if self.index.is_multiple_of(schedule.every.max(1)) => schedule,
+            _ => return Ok(()),
+        };
+        // The live blocks are only known if the shadow heap is enabled.
+        let live = self.shadow.as_ref().map_or(Vec::new(), ShadowHeap::live);
+        let problems = heapcheck::check(&mut self.allocator, &live, schedule.align)?.unwrap_or_default();
+        self.violations.extend(problems.into_iter().map(|problem| Violation {
+            index: self.index,
+            kind: ViolationKind::Inconsistency,
+            message: problem.to_string(),
+            blocks: Vec::new(),
+        }));
+        Ok(())
+    }
+
+    // write writes bytes directly into the memory.
+    fn write(&mut self, address: i32, bytes: &[u8]) -> Result<Outcome, ExecError> {
+        self.allocator.write_bytes(address as u32, bytes)?;
+        self.track(|shadow, index| shadow.access(index, "write", address, bytes.len() as u32));
+        self.watch(|canary, _, _| {
+            canary.store(address, bytes);
+            Vec::new()
+        });
+        Ok(Outcome::Done)
+    }
+
+    // dump returns an annotated dump of `len` bytes at `address`, labeling
+    // blocks with their handles. Without the heap walk, the blocks are known
+    // only if the shadow heap is enabled.
+    fn dump(&mut self, address: u32, len: usize) -> Result<String, Error> {
+        let bytes = self.allocator.read_bytes(address, len)?;
+        let mut names: HashMap<u64, Vec<String>> = HashMap::new();
+        for (name, &block) in &self.handles {
+            names.entry(block as u32 as u64).or_default().push(format!("${}", name));
+        }
+        for names in names.values_mut() {
+            names.sort();
+        }
+        let live = self.shadow.as_ref().map_or(Vec::new(), ShadowHeap::live);
+        let marks = dump::marks(&mut self.allocator, &live, &names)?;
+        Ok(dump::annotated(address, &bytes, &marks))
+    }
+
+    // execute runs a single statement, then checks the heap if asked to.
+    pub fn execute(&mut self, statement: &Statement) -> Result<Outcome, ExecError> {
+        self.index += 1;
+        let outcome = self.apply(statement)?;
+        self.check_heap()?;
+        Ok(outcome)
+    }
+
+    fn apply(&mut self, statement: &Statement) -> Result<Outcome, ExecError> {
+        match &statement.command {
+            Command::Alloc(size) => {
+                let size = self.resolve(size)?;
+                let address = self.allocator.alloc(size)?;
+                self.track(|shadow, index| shadow.alloc(index, size, address));
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.alloc(size as u32 as u64, address as u32 as u64);
+                }
+                self.watch(|canary, memory, index| {
+                    let after = format!("alloc({}) = {}", size, address);
+                    let violations = canary.check_all(index, memory, &after);
+                    canary.alloc(memory, address, size);
+                    violations
+                });
+                let name = self.bind_name(statement);
+                self.handles.insert(name.clone(), address);
+                Ok(Outcome::Bound(name, address))
+            }
+            Command::Dealloc(address) => {
+                let address = self.resolve(address)?;
+                self.watch(|canary, memory, index| {
+                    canary.check(index, memory, address, "its last use").into_iter().collect()
+                });
+                self.allocator.dealloc(address)?;
+                self.track(|shadow, index| shadow.dealloc(index, address));
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.dealloc(address as u32 as u64);
+                }
+                self.watch(|canary, memory, index| {
+                    canary.dealloc(address);
+                    canary.check_all(index, memory, &format!("dealloc({})", address))
+                });
+                Ok(Outcome::Done)
+            }
+            Command::Realloc(address, size) => {
+                let address = self.resolve(address)?;
+                let size = self.resolve(size)?;
+                self.watch(|canary, memory, index| {
+                    canary.check(index, memory, address, "its last use").into_iter().collect()
+                });
+                let new = self.allocator.realloc(address, size)?;
+                self.track(|shadow, index| shadow.realloc(index, address, size, new));
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.realloc(address as u32 as u64, size as u32 as u64, new as u32 as u64);
+                }
+                self.watch(|canary, memory, index| {
+                    let mut violations = canary.realloc(index, memory, address, size, new);
+                    let after = format!("realloc({}, {}) = {}", address, size, new);
+                    violations.extend(canary.check_all(index, memory, &after));
+                    violations
+                });
+                let name = self.bind_name(statement);
+                self.handles.insert(name.clone(), new);
+                Ok(Outcome::Bound(name, new))
+            }
+            Command::Store(address, value) => {
+                let address = self.resolve(address)?;
+                let value = self.resolve(value)?;
+                self.allocator.store(address, value)?;
+                self.track(|shadow, index| shadow.access(index, "store", address, 4));
+                self.watch(|canary, _, _| {
+                    canary.store(address, &value.to_le_bytes());
+                    Vec::new()
+                });
+                Ok(Outcome::Done)
+            }
+            Command::Load(address) => {
+                let address = self.resolve(address)?;
+                let value = self.allocator.load(address)?;
+                self.track(|shadow, index| shadow.access(index, "load", address, 4));
+                Ok(Outcome::Value(value))
+            }
+            Command::Size => Ok(Outcome::Value(self.allocator.memory_pages()? as i32)),
+            Command::Grow(pages) => {
+                let pages = self.resolve(pages)?;
+                Ok(Outcome::Value(self.allocator.grow(pages)?))
+            }
+            Command::Read(address, len) => {
+                let address = self.resolve(address)?;
+                let len = self.resolve_len(len)?;
+                let bytes = self.allocator.read_bytes(address as u32, len)?;
+                self.track(|shadow, index| shadow.access(index, "read", address, len as u32));
+                Ok(Outcome::Bytes(address as u32, bytes))
+            }
+            Command::Write(address, bytes) => {
+                let address = self.resolve(address)?;
+                let mut values = Vec::new();
+                for byte in bytes {
+                    match self.resolve(byte)? {
+                        value @ -128..=255 => values.push(value as u8),
+                        value => return Err(ExecError::Operand(format!("{} doesn't fit in a byte", value))),
+                    }
+                }
+                self.write(address, &values)
+            }
+            Command::ReadInt(bits, address) => {
+                let address = self.resolve(address)?;
+                let value = match bits {
+                    8 => self.allocator.read_int::<u8>(address as u32)? as i32,
+                    16 => self.allocator.read_int::<u16>(address as u32)? as i32,
+                    _ => self.allocator.read_int::<i32>(address as u32)?,
+                };
+                self.track(|shadow, index| shadow.access(index, "read", address, bits / 8));
+                Ok(Outcome::Value(value))
+            }
+            Command::WriteInt(bits, address, value) => {
+                let address = self.resolve(address)?;
+                let value = self.resolve(value)?;
+                self.write(address, &value.to_le_bytes()[..*bits as usize / 8])
+            }
+            Command::Dump(address, len) => {
+                let address = self.resolve(address)? as u32;
+                let len = self.resolve_len(len)?;
+                Ok(Outcome::Dump(self.dump(address, len)?))
+            }
+        }
+    }
+}
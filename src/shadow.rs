@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+// ShadowHeap is a host-side record of the blocks an allocator handed out. It's
+// told about every alloc, dealloc, realloc, store and load after they happen,
+// and flags the ones that break the rules of dynamic memory: overlapping live
+// blocks, double frees, frees and reallocs of addresses that were never
+// allocated, and accesses to freed or unallocated memory.
+
+// Event is an entry in the history of a block.
+#[derive(Debug, Clone)]
+pub struct Event {
+    // index is the index of the operation that caused the event.
+    pub index: usize,
+    pub description: String,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "op {}: {}", self.index, self.description)
+    }
+}
+
+// Block is a block returned by alloc or realloc, live or freed.
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub address: i32,
+    // size is the requested size in bytes.
+    pub size: i32,
+    // history lists what happened to the block, starting with its allocation.
+    // Blocks moved by realloc carry over the history of the original block.
+    pub history: Vec<Event>,
+}
+
+impl Block {
+    fn start(&self) -> u64 {
+        self.address as u32 as u64
+    }
+
+    fn end(&self) -> u64 {
+        self.start() + self.size as u32 as u64
+    }
+
+    fn contains(&self, start: u64, end: u64) -> bool {
+        self.start() <= start && end <= self.end()
+    }
+
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start() < end && start < self.end()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViolationKind {
+    Overlap,
+    DoubleFree,
+    InvalidFree,
+    ReallocOfFreed,
+    InvalidRealloc,
+    UseAfterFree,
+    InvalidAccess,
+    Corruption,
+    Inconsistency,
+}
+
+impl fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ViolationKind::Overlap => "overlapping blocks",
+            ViolationKind::DoubleFree => "double free",
+            ViolationKind::InvalidFree => "invalid free",
+            ViolationKind::ReallocOfFreed => "realloc of freed block",
+            ViolationKind::InvalidRealloc => "invalid realloc",
+            ViolationKind::UseAfterFree => "use after free",
+            ViolationKind::InvalidAccess => "invalid access",
+            ViolationKind::Corruption => "corruption",
+            ViolationKind::Inconsistency => "inconsistent heap",
+        })
+    }
+}
+
+// Violation is a broken rule, with the history of the blocks involved.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub index: usize,
+    pub kind: ViolationKind,
+    pub message: String,
+    pub blocks: Vec<Block>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "op {}: {}: {}", self.index, self.kind, self.message)?;
+        for block in &self.blocks {
+            write!(f, "\n  block [{}, {}) history:", block.start(), block.end())?;
+            for event in &block.history {
+                write!(f, "\n    {}", event)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct ShadowHeap {
+    // live maps the addresses of live blocks to the blocks.
+    live: BTreeMap<u32, Block>,
+    // freed maps the addresses of freed blocks to the blocks, until another
+    // block is allocated over them.
+    freed: BTreeMap<u32, Block>,
+}
+
+impl ShadowHeap {
+    pub fn new() -> ShadowHeap {
+        ShadowHeap::default()
+    }
+
+    // place records a block returned by alloc or realloc, checking it doesn't
+    // overlap any live block.
+    fn place(&mut self, block: Block, index: usize, violations: &mut Vec<Violation>) {
+        let (start, end) = (block.start(), block.end());
+        for other in self.live.values().filter(|other| other.overlaps(start, end)) {
+            violations.push(Violation {
+                index,
+                kind: ViolationKind::Overlap,
+                message: format!(
+                    "block [{}, {}) overlaps live block [{}, {})",
+                    start,
+                    end,
+                    other.start(),
+                    other.end()
+                ),
+                blocks: vec![block.clone(), other.clone()],
+            });
+        }
+        // Memory that's allocated again is no longer freed.
+        self.freed.retain(|_, freed| !freed.overlaps(start, end.max(start + 1)));
+        if block.size != 0 {
+            self.live.insert(block.address as u32, block);
+        }
+    }
+
+    // live returns the live blocks as (address, size).
+    pub fn live(&self) -> Vec<(u64, u64)> {
+        self.live.values().map(|block| (block.start(), block.end() - block.start())).collect()
+    }
+
+    // alloc records that alloc(size) returned the address.
+    pub fn alloc(&mut self, index: usize, size: i32, address: i32) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        if size == 0 {
+            return violations;
+        }
+        let history = vec![Event { index, description: format!("alloc({}) = {}", size, address) }];
+        self.place(Block { address, size, history }, index, &mut violations);
+        violations
+    }
+
+    // dealloc records that dealloc(address) was called.
+    pub fn dealloc(&mut self, index: usize, address: i32) -> Vec<Violation> {
+        let key = address as u32;
+        if let Some(mut block) = self.live.remove(&key) {
+            block.history.push(Event { index, description: format!("dealloc({})", address) });
+            self.freed.insert(key, block);
+            return Vec::new();
+        }
+        let violation = match self.freed.get(&key) {
+            Some(block) => Violation {
+                index,
+                kind: ViolationKind::DoubleFree,
+                message: format!("dealloc({}) of a block that was already freed", address),
+                blocks: vec![block.clone()],
+            },
+            // Like free(NULL), deallocating address zero is allowed.
+            None if address == 0 => return Vec::new(),
+            None => Violation {
+                index,
+                kind: ViolationKind::InvalidFree,
+                message: format!("dealloc({}) of an address never returned by alloc", address),
+                blocks: self.containing(key as u64, key as u64 + 1),
+            },
+        };
+        vec![violation]
+    }
+
+    // realloc records that realloc(address, size) returned the new address.
+    pub fn realloc(&mut self, index: usize, address: i32, size: i32, new: i32) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let key = address as u32;
+        let description = format!("realloc({}, {}) = {}", address, size, new);
+        let mut history = match self.live.remove(&key) {
+            Some(block) => {
+                // A block that moved leaves its old memory freed.
+                if new != address {
+                    let mut old = block.clone();
+                    old.history.push(Event { index, description: description.clone() });
+                    self.freed.insert(key, old);
+                }
+                block.history
+            }
+            None => {
+                violations.push(match self.freed.get(&key) {
+                    Some(block) => Violation {
+                        index,
+                        kind: ViolationKind::ReallocOfFreed,
+                        message: format!("realloc({}, {}) of a block that was freed", address, size),
+                        blocks: vec![block.clone()],
+                    },
+                    None => Violation {
+                        index,
+                        kind: ViolationKind::InvalidRealloc,
+                        message: format!("realloc({}, {}) of an address never returned by alloc", address, size),
+                        blocks: self.containing(key as u64, key as u64 + 1),
+                    },
+                });
+                Vec::new()
+            }
+        };
+        history.push(Event { index, description });
+        self.place(Block { address: new, size, history }, index, &mut violations);
+        violations
+    }
+
+    // access records a load or store (`what`) of `len` bytes at the address.
+    pub fn access(&mut self, index: usize, what: &str, address: i32, len: u32) -> Vec<Violation> {
+        let start = address as u32 as u64;
+        let end = start + len as u64;
+        if self.live.values().any(|block| block.contains(start, end)) {
+            return Vec::new();
+        }
+        let freed: Vec<Block> = self.freed.values().filter(|block| block.overlaps(start, end)).cloned().collect();
+        let violation = if !freed.is_empty() {
+            Violation {
+                index,
+                kind: ViolationKind::UseAfterFree,
+                message: format!("{} of [{}, {}) in freed memory", what, start, end),
+                blocks: freed,
+            }
+        } else {
+            Violation {
+                index,
+                kind: ViolationKind::InvalidAccess,
+                message: format!("{} of [{}, {}) outside any live block", what, start, end),
+                blocks: self.containing(start, end),
+            }
+        };
+        vec![violation]
+    }
+
+    // containing returns the live blocks that overlap [start, end).
+    fn containing(&self, start: u64, end: u64) -> Vec<Block> {
+        self.live.values().filter(|block| block.overlaps(start, end)).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // kinds returns the kinds of the violations.
+    fn kinds(violations: Vec<Violation>) -> Vec<ViolationKind> {
+        violations.iter().map(|violation| violation.kind).collect()
+    }
+
+    #[test]
+    fn valid_operations_pass() {
+        let mut shadow = ShadowHeap::new();
+        assert!(shadow.alloc(0, 16, 100).is_empty());
+        assert!(shadow.alloc(1, 0, 0).is_empty());
+        assert!(shadow.access(2, "store", 112, 4).is_empty());
+        assert!(shadow.realloc(3, 100, 32, 200).is_empty());
+        assert!(shadow.alloc(4, 8, 100).is_empty());
+        assert!(shadow.dealloc(5, 0).is_empty());
+        assert_eq!(shadow.live(), [(100, 8), (200, 32)]);
+    }
+
+    #[test]
+    fn overlap() {
+        let mut shadow = ShadowHeap::new();
+        shadow.alloc(0, 16, 100);
+        let violations = shadow.alloc(1, 8, 112);
+        assert_eq!(kinds(violations.clone()), [ViolationKind::Overlap]);
+        assert_eq!(violations[0].message, "block [112, 120) overlaps live block [100, 116)");
+        assert_eq!(violations[0].blocks.len(), 2);
+        assert_eq!(kinds(shadow.realloc(2, 112, 8, 104)), [ViolationKind::Overlap]);
+    }
+
+    #[test]
+    fn frees() {
+        let mut shadow = ShadowHeap::new();
+        shadow.alloc(0, 16, 100);
+        assert!(shadow.dealloc(1, 100).is_empty());
+        let violations = shadow.dealloc(2, 100);
+        assert_eq!(kinds(violations.clone()), [ViolationKind::DoubleFree]);
+        // The history of the block leads up to the violation.
+        let history: Vec<String> = violations[0].blocks[0].history.iter().map(Event::to_string).collect();
+        assert_eq!(history, ["op 0: alloc(16) = 100", "op 1: dealloc(100)"]);
+        assert_eq!(kinds(shadow.dealloc(3, 104)), [ViolationKind::InvalidFree]);
+    }
+
+    #[test]
+    fn reallocs() {
+        let mut shadow = ShadowHeap::new();
+        shadow.alloc(0, 16, 100);
+        shadow.realloc(1, 100, 32, 200);
+        assert_eq!(kinds(shadow.realloc(2, 100, 8, 300)), [ViolationKind::ReallocOfFreed]);
+        assert_eq!(kinds(shadow.realloc(3, 400, 8, 500)), [ViolationKind::InvalidRealloc]);
+    }
+
+    #[test]
+    fn accesses() {
+        let mut shadow = ShadowHeap::new();
+        shadow.alloc(0, 16, 100);
+        shadow.dealloc(1, 100);
+        shadow.alloc(2, 8, 200);
+        assert_eq!(kinds(shadow.access(3, "load", 104, 4)), [ViolationKind::UseAfterFree]);
+        // An access straddling the end of a live block is invalid.
+        let violations = shadow.access(4, "store", 206, 4);
+        assert_eq!(kinds(violations.clone()), [ViolationKind::InvalidAccess]);
+        assert_eq!(violations[0].message, "store of [206, 210) outside any live block");
+        assert_eq!(violations[0].blocks.len(), 1);
+        // Allocating over freed memory makes it unallocated rather than freed.
+        shadow.alloc(5, 4, 100);
+        assert_eq!(kinds(shadow.access(6, "load", 108, 4)), [ViolationKind::InvalidAccess]);
+    }
+}
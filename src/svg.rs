@@ -0,0 +1,115 @@
+// Helpers to draw simple charts as SVG, so that reports are self-contained.
+
+// PALETTE holds the colors of the series of a chart, in order.
+pub const PALETTE: [&str; 8] = ["#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2", "#17becf"];
+
+const WIDTH: f64 = 640.0;
+const HEIGHT: f64 = 300.0;
+const LEFT: f64 = 80.0;
+const RIGHT: f64 = 20.0;
+const TOP: f64 = 30.0;
+const BOTTOM: f64 = 45.0;
+const TICKS: usize = 5;
+
+// escape escapes text for use in SVG and HTML.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Series is a named line of a chart.
+pub struct Series {
+    pub name: String,
+    pub points: Vec<(f64, f64)>,
+}
+
+// Chart describes a line chart. The y axis starts at zero, and is logarithmic
+// if `log` is set, for values with a long tail.
+pub struct Chart<'a> {
+    pub title: &'a str,
+    pub x_label: &'a str,
+    pub format_x: &'a dyn Fn(f64) -> String,
+    pub format_y: &'a dyn Fn(f64) -> String,
+    pub log: bool,
+}
+
+impl Chart<'_> {
+    // draw returns the chart of the series as an SVG element.
+    pub fn draw(&self, series: &[Series]) -> String {
+        let points = series.iter().flat_map(|series| &series.points);
+        // scale maps values to the y axis, and unscale maps them back.
+        let scale = |y: f64| if self.log { y.max(0.0).ln_1p() } else { y };
+        let unscale = |y: f64| if self.log { y.exp_m1() } else { y };
+        let (mut x_min, mut x_max, mut y_max) = (f64::MAX, f64::MIN, 0.0f64);
+        for &(x, y) in points {
+            x_min = x_min.min(x);
+            x_max = x_max.max(x);
+            y_max = y_max.max(scale(y));
+        }
+        if x_min > x_max {
+            (x_min, x_max) = (0.0, 1.0);
+        }
+        if x_max == x_min {
+            x_max = x_min + 1.0;
+        }
+        if y_max == 0.0 {
+            y_max = 1.0;
+        }
+        y_max *= 1.05;
+        let (plot_width, plot_height) = (WIDTH - LEFT - RIGHT, HEIGHT - TOP - BOTTOM);
+        let x_pos = |x: f64| LEFT + (x - x_min) / (x_max - x_min) * plot_width;
+        let y_pos = |y: f64| TOP + plot_height - scale(y) / y_max * plot_height;
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"sans-serif\" font-size=\"11\">\n",
+            WIDTH, HEIGHT, WIDTH, HEIGHT
+        );
+        svg += &format!("<text x=\"{}\" y=\"18\" font-size=\"13\" font-weight=\"bold\">{}</text>\n", LEFT, escape(self.title));
+        for i in 0..=TICKS {
+            let fraction = i as f64 / TICKS as f64;
+            let y = TOP + plot_height - fraction * plot_height;
+            svg += &format!(
+                "<line x1=\"{}\" y1=\"{:.1}\" x2=\"{}\" y2=\"{:.1}\" stroke=\"#ddd\"/><text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>\n",
+                LEFT,
+                y,
+                WIDTH - RIGHT,
+                y,
+                LEFT - 5.0,
+                y + 4.0,
+                escape(&(self.format_y)(unscale(y_max * fraction)))
+            );
+            let x = x_min + (x_max - x_min) * fraction;
+            svg += &format!(
+                "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n",
+                x_pos(x),
+                HEIGHT - BOTTOM + 15.0,
+                escape(&(self.format_x)(x))
+            );
+        }
+        svg += &format!(
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#000\"/>\n<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n",
+            LEFT,
+            TOP + plot_height,
+            WIDTH - RIGHT,
+            TOP + plot_height,
+            LEFT + plot_width / 2.0,
+            HEIGHT - 8.0,
+            escape(self.x_label)
+        );
+        for (i, series) in series.iter().enumerate() {
+            let color = PALETTE[i % PALETTE.len()];
+            let path: Vec<String> = series.points.iter().map(|&(x, y)| format!("{:.1},{:.1}", x_pos(x), y_pos(y))).collect();
+            svg += &format!("<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>\n", color, path.join(" "));
+            let y = TOP + 12.0 + i as f64 * 14.0;
+            svg += &format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"10\" height=\"10\" fill=\"{}\"/><text x=\"{}\" y=\"{}\">{}</text>\n",
+                LEFT + 10.0,
+                y - 9.0,
+                color,
+                LEFT + 25.0,
+                y,
+                escape(&series.name)
+            );
+        }
+        svg + "</svg>"
+    }
+}
@@ -0,0 +1,84 @@
+use crate::heapmap;
+use crate::ops::Op;
+use crate::svg::escape;
+use std::error::Error;
+
+// A timeline animates the heap map of a module over a workload: it's a single
+// HTML file holding a map per frame, with a player to step through them or
+// play them back, to watch blocks being carved, freed and coalesced.
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+.controls { display: flex; align-items: center; gap: 0.5em; margin: 1em 0; }
+.controls input[type=range] { width: 30em; }
+#caption { font-family: monospace; }";
+
+// The player shows one frame at a time, and plays them at the chosen speed.
+const SCRIPT: &str = "\
+const frames = document.querySelectorAll('.frame');
+const slider = document.getElementById('slider');
+const caption = document.getElementById('caption');
+const play = document.getElementById('play');
+let current = 0, timer = null;
+function show(index) {
+  frames[current].hidden = true;
+  current = Math.max(0, Math.min(frames.length - 1, index));
+  frames[current].hidden = false;
+  slider.value = current;
+  caption.textContent = frames[current].dataset.caption;
+}
+function stop() { clearInterval(timer); timer = null; play.textContent = 'Play'; }
+play.onclick = () => {
+  if (timer) return stop();
+  if (current == frames.length - 1) show(0);
+  play.textContent = 'Pause';
+  timer = setInterval(() => current == frames.length - 1 ? stop() : show(current + 1), 1000 / document.getElementById('speed').value);
+};
+document.getElementById('previous').onclick = () => { stop(); show(current - 1); };
+document.getElementById('next').onclick = () => { stop(); show(current + 1); };
+document.getElementById('speed').onchange = () => { if (timer) { stop(); play.click(); } };
+slider.oninput = () => { stop(); show(Number(slider.value)); };
+document.onkeydown = (event) => {
+  if (event.key == 'ArrowLeft') document.getElementById('previous').click();
+  if (event.key == 'ArrowRight') document.getElementById('next').click();
+  if (event.key == ' ') { event.preventDefault(); play.click(); }
+};
+show(0);";
+
+// indices returns the indices of the operations after which frames are taken,
+// evenly spaced over the workload and always including the last operation.
+fn indices(count: usize, frames: usize) -> Vec<usize> {
+    let frames = frames.clamp(1, count.max(1));
+    let mut indices: Vec<usize> = (1..=frames).map(|frame| (count * frame / frames).saturating_sub(1)).collect();
+    indices.dedup();
+    indices
+}
+
+// run replays the workload against the module and returns the timeline as
+// HTML, with `frames` frames of maps with `width` cells per row. The cell size
+// is picked to fit the final layout, unless `cell` is set.
+pub fn run(module: &str, name: &str, ops: &[Op], frames: usize, width: u64, cell: Option<u64>) -> Result<String, Box<dyn Error>> {
+    let layouts = heapmap::capture(module, ops, &indices(ops.len(), frames))?;
+    let cell = cell.unwrap_or_else(|| layouts.iter().map(|layout| heapmap::cell_size(layout, width, 32)).max().unwrap_or(1));
+    // Every frame has the same rows so that the maps don't jump around.
+    let rows = layouts.iter().map(|layout| heapmap::rows(layout, width, cell)).max().unwrap_or(1);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>wasmalloc timeline</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>{} on {}</h1>\n",
+        STYLE,
+        escape(module),
+        escape(name)
+    );
+    html += &format!(
+        "<div class=\"controls\">\n<button id=\"previous\">&lt;</button><button id=\"play\">Play</button><button id=\"next\">&gt;</button>\n<input id=\"slider\" type=\"range\" min=\"0\" max=\"{}\" value=\"0\">\n<select id=\"speed\"><option value=\"2\">2 fps</option><option value=\"5\" selected>5 fps</option><option value=\"10\">10 fps</option><option value=\"25\">25 fps</option></select>\n</div>\n<p id=\"caption\"></p>\n",
+        layouts.len() - 1
+    );
+    for (frame, layout) in layouts.iter().enumerate() {
+        let caption = format!("frame {}/{}: after op {} of {}, {}", frame + 1, layouts.len(), layout.index, ops.len(), ops[layout.index]);
+        html += &format!(
+            "<div class=\"frame\" hidden data-caption=\"{}\">\n{}</div>\n",
+            escape(&caption),
+            heapmap::svg(std::slice::from_ref(layout), width, cell, Some(rows))
+        );
+    }
+    Ok(html + &format!("<script>\n{}\n</script>\n</body>\n</html>\n", SCRIPT))
+}
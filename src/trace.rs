@@ -0,0 +1,303 @@
+use crate::ops::Op;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// A trace is a sequence of allocation events (alloc, free and realloc) on
+// logical blocks, identified by ids instead of addresses so that it can be
+// replayed against any allocator. Traces have a text and a binary format.
+//
+// The text format has a header line followed by one event per line:
+//
+//     wasmalloc-trace 1
+//     a 0 24       # alloc block 0 with 24 bytes
+//     a 1 8
+//     r 0 100      # realloc block 0 to 100 bytes
+//     f 1          # free block 1
+//
+// The binary format is the magic bytes "WATR", a version byte, and then one
+// event per tag byte (0 alloc, 1 free, 2 realloc) followed by the block id
+// and, for alloc and realloc, the size, both as unsigned LEB128 numbers.
+//
+// In both formats, sizes are unsigned 32-bit numbers.
+
+const TEXT_HEADER: &str = "wasmalloc-trace 1";
+const BINARY_MAGIC: &[u8] = b"WATR";
+const BINARY_VERSION: u8 = 1;
+
+// encode_text encodes the allocation events in the text format.
+pub fn encode_text(ops: &[Op]) -> String {
+    let mut text = format!("{}\n", TEXT_HEADER);
+    for op in ops {
+        match *op {
+            Op::Alloc { id, size } => text += &format!("a {} {}\n", id, size as u32),
+            Op::Dealloc { id } => text += &format!("f {}\n", id),
+            Op::Realloc { id, size } => text += &format!("r {} {}\n", id, size as u32),
+            Op::Store { .. } | Op::Load { .. } => {}
+        }
+    }
+    text
+}
+
+// size_from converts a size read from a trace to the 32-bit size of an Op,
+// or returns None if it's out of range.
+fn size_from(size: u64) -> Option<i32> {
+    u32::try_from(size).ok().map(|size| size as i32)
+}
+
+// parse_text parses a trace in the text format.
+pub fn parse_text(text: &str) -> Result<Vec<Op>, String> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim() == TEXT_HEADER => {}
+        _ => return Err(format!("missing '{}' header", TEXT_HEADER)),
+    }
+    let mut ops = Vec::new();
+    for (i, line) in lines {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let number = |field: &str| -> Result<u64, String> {
+            field.parse().map_err(|_| format!("line {}: invalid number '{}'", i + 1, field))
+        };
+        let parse_size = |field: &str| -> Result<i32, String> {
+            let size = number(field)?;
+            size_from(size).ok_or_else(|| format!("line {}: size {} doesn't fit in 32 bits", i + 1, size))
+        };
+        let op = match fields[..] {
+            ["a", id, size] => Op::Alloc { id: number(id)? as usize, size: parse_size(size)? },
+            ["f", id] => Op::Dealloc { id: number(id)? as usize },
+            ["r", id, size] => Op::Realloc { id: number(id)? as usize, size: parse_size(size)? },
+            _ => return Err(format!("line {}: invalid event '{}'", i + 1, line)),
+        };
+        ops.push(op);
+    }
+    Ok(ops)
+}
+
+fn write_leb128(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn read_leb128(bytes: &mut impl Iterator<Item = u8>) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = bytes.next().ok_or("unexpected end of trace")?;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("number too large".to_string())
+}
+
+// encode_binary encodes the allocation events in the binary format.
+pub fn encode_binary(ops: &[Op]) -> Vec<u8> {
+    let mut bytes = BINARY_MAGIC.to_vec();
+    bytes.push(BINARY_VERSION);
+    for op in ops {
+        match *op {
+            Op::Alloc { id, size } => {
+                bytes.push(0);
+                write_leb128(&mut bytes, id as u64);
+                write_leb128(&mut bytes, size as u32 as u64);
+            }
+            Op::Dealloc { id } => {
+                bytes.push(1);
+                write_leb128(&mut bytes, id as u64);
+            }
+            Op::Realloc { id, size } => {
+                bytes.push(2);
+                write_leb128(&mut bytes, id as u64);
+                write_leb128(&mut bytes, size as u32 as u64);
+            }
+            Op::Store { .. } | Op::Load { .. } => {}
+        }
+    }
+    bytes
+}
+
+// decode_binary decodes a trace in the binary format.
+pub fn decode_binary(bytes: &[u8]) -> Result<Vec<Op>, String> {
+    let rest = bytes.strip_prefix(BINARY_MAGIC).ok_or("missing binary trace magic")?;
+    match rest.first() {
+        Some(&BINARY_VERSION) => {}
+        Some(version) => return Err(format!("unsupported binary trace version {}", version)),
+        None => return Err("unexpected end of trace".to_string()),
+    }
+    let mut bytes = rest[1..].iter().copied();
+    let mut ops = Vec::new();
+    while let Some(tag) = bytes.next() {
+        let id = read_leb128(&mut bytes)? as usize;
+        let event = ops.len();
+        let mut read_size = || -> Result<i32, String> {
+            let size = read_leb128(&mut bytes)?;
+            size_from(size).ok_or_else(|| format!("size {} doesn't fit in 32 bits at event {}", size, event))
+        };
+        let op = match tag {
+            0 => Op::Alloc { id, size: read_size()? },
+            1 => Op::Dealloc { id },
+            2 => Op::Realloc { id, size: read_size()? },
+            _ => return Err(format!("invalid event tag {} at event {}", tag, ops.len())),
+        };
+        ops.push(op);
+    }
+    Ok(ops)
+}
+
+// load reads a trace file in either format, detected from its contents.
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Op>, Box<dyn Error>> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let ops = if bytes.starts_with(BINARY_MAGIC) {
+        decode_binary(&bytes)
+    } else {
+        parse_text(&String::from_utf8_lossy(&bytes))
+    };
+    Ok(ops.map_err(|err| format!("{}: {}", path.display(), err))?)
+}
+
+// save writes the allocation events of `ops` to a trace file, in the binary
+// format if the path ends in `.bin` and in the text format otherwise.
+pub fn save(path: impl AsRef<Path>, ops: &[Op]) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    let bytes = match path.extension() {
+        Some(extension) if extension == "bin" => encode_binary(ops),
+        _ => encode_text(ops).into_bytes(),
+    };
+    fs::write(path, bytes).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(())
+}
+
+// Recorder records allocator calls made with real addresses as trace events,
+// giving each block a logical id. Addresses are 64-bit so that traces of
+// native programs can be recorded too.
+#[derive(Default)]
+pub struct Recorder {
+    pub ops: Vec<Op>,
+    // ids maps the addresses of live blocks to their ids.
+    ids: HashMap<u64, usize>,
+    next_id: usize,
+}
+
+// clamp_size converts a native request size to the 32-bit sizes of a trace.
+fn clamp_size(size: u64) -> i32 {
+    size.min(u32::MAX as u64) as u32 as i32
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    pub fn alloc(&mut self, size: u64, address: u64) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.ops.push(Op::Alloc { id, size: clamp_size(size) });
+        if size != 0 {
+            self.ids.insert(address, id);
+        }
+    }
+
+    // dealloc records a dealloc. Addresses that don't belong to a recorded
+    // block can't be given an id, so they're left out of the trace.
+    pub fn dealloc(&mut self, address: u64) {
+        if let Some(id) = self.ids.remove(&address) {
+            self.ops.push(Op::Dealloc { id });
+        }
+    }
+
+    // realloc records a realloc, or an alloc if the address doesn't belong to
+    // a recorded block.
+    pub fn realloc(&mut self, address: u64, size: u64, new: u64) {
+        match self.ids.remove(&address) {
+            Some(id) => {
+                self.ops.push(Op::Realloc { id, size: clamp_size(size) });
+                self.ids.insert(new, id);
+            }
+            None => self.alloc(size, new),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops;
+    use crate::rng::Rng;
+
+    // events returns random allocation events, with the loads and stores the
+    // traces leave out removed.
+    fn events() -> Vec<Op> {
+        let mut ops = ops::random(&mut Rng::new(3), 2000);
+        ops.retain(|op| !matches!(op, Op::Store { .. } | Op::Load { .. }));
+        ops.push(Op::Alloc { id: usize::MAX >> 1, size: u32::MAX as i32 });
+        ops.push(Op::Realloc { id: 0, size: 0 });
+        ops
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let ops = events();
+        assert_eq!(parse_text(&encode_text(&ops)).unwrap(), ops);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let ops = events();
+        assert_eq!(decode_binary(&encode_binary(&ops)).unwrap(), ops);
+    }
+
+    #[test]
+    fn save_and_load_both_formats() {
+        let ops = events();
+        let dir = std::env::temp_dir();
+        for name in ["wasmalloc-test.trace", "wasmalloc-test.bin"] {
+            let path = dir.join(format!("{}-{}", std::process::id(), name));
+            save(&path, &ops).unwrap();
+            let loaded = load(&path);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.unwrap(), ops, "{}", name);
+        }
+    }
+
+    #[test]
+    fn text_comments_and_blank_lines() {
+        let text = "wasmalloc-trace 1\n\n# a comment\na 0 24  # alloc\nr 0 100\nf 0\n";
+        let expected = vec![Op::Alloc { id: 0, size: 24 }, Op::Realloc { id: 0, size: 100 }, Op::Dealloc { id: 0 }];
+        assert_eq!(parse_text(text).unwrap(), expected);
+    }
+
+    #[test]
+    fn text_errors_have_line_numbers() {
+        let error = |text: &str| parse_text(text).unwrap_err();
+        assert_eq!(error("a 0 24\n"), "missing 'wasmalloc-trace 1' header");
+        assert_eq!(error("wasmalloc-trace 1\na 0 24\nx 1\n"), "line 3: invalid event 'x 1'");
+        assert_eq!(error("wasmalloc-trace 1\na 0 -1\n"), "line 2: invalid number '-1'");
+        assert_eq!(error("wasmalloc-trace 1\n\nr 0 4294967296\n"), "line 3: size 4294967296 doesn't fit in 32 bits");
+    }
+
+    #[test]
+    fn binary_errors() {
+        let mut bytes = encode_binary(&[Op::Alloc { id: 0, size: 8 }]);
+        assert_eq!(decode_binary(&bytes[..bytes.len() - 1]).unwrap_err(), "unexpected end of trace");
+        bytes.extend([2, 0]);
+        write_leb128(&mut bytes, u32::MAX as u64 + 1);
+        assert_eq!(decode_binary(&bytes).unwrap_err(), "size 4294967296 doesn't fit in 32 bits at event 1");
+        bytes.truncate(BINARY_MAGIC.len());
+        bytes.push(BINARY_VERSION + 1);
+        assert_eq!(decode_binary(&bytes).unwrap_err(), format!("unsupported binary trace version {}", BINARY_VERSION + 1));
+        assert_eq!(decode_binary(b"WATR\x01\x07\x00").unwrap_err(), "invalid event tag 7 at event 0");
+    }
+}
@@ -0,0 +1,397 @@
+use crate::ops::Op;
+use crate::rng::{Rng, Source};
+use crate::trace;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+// A workload describes a synthetic allocation pattern: how large the requests
+// are, how long blocks live, and how blocks grow through realloc. Generating
+// it with a seed produces a deterministic sequence of alloc, free and realloc
+// operations, which can be replayed or saved as a trace.
+//
+// Every part has a text form, used on the command line and in reports:
+//
+//     sizes:    uniform:1-1024, power-law:16-65536:2, bimodal:16-64:4096-16384:10
+//               (the last number is the percentage of large requests) or
+//               classes:16:32:64 (fixed size classes, equally likely)
+//     lifetime: lifo, fifo, random or mixed:10 (10% of the blocks are long-lived
+//               and only freed when no short-lived block is left)
+//     growth:   none, linear:64 (grow by 64 bytes) or geometric:2 (double)
+
+// Sizes is a distribution of request sizes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sizes {
+    Uniform { min: i32, max: i32 },
+    // PowerLaw favors small sizes, with the probability of a size falling as
+    // size^-exponent.
+    PowerLaw { min: i32, max: i32, exponent: f64 },
+    // Bimodal mixes small and large requests.
+    Bimodal { small: (i32, i32), large: (i32, i32), large_percent: u64 },
+    Classes(Vec<i32>),
+}
+
+// Lifetime decides which live block is freed next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lifetime {
+    // Lifo frees the most recently allocated block, like a stack.
+    Lifo,
+    // Fifo frees the least recently allocated block, like a queue.
+    Fifo,
+    Random,
+    // Mixed makes a percentage of the blocks long-lived, and frees the others
+    // in random order.
+    Mixed { long_lived_percent: u64 },
+}
+
+// Growth decides the new size of a block that is reallocated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Growth {
+    None,
+    Linear { step: i32 },
+    Geometric { factor: f64 },
+}
+
+// parse_range parses `low-high`.
+fn parse_range(text: &str) -> Result<(i32, i32), String> {
+    let (low, high) = text.split_once('-').ok_or_else(|| format!("invalid range '{}'", text))?;
+    let number = |text: &str| text.parse::<i32>().map_err(|_| format!("invalid size '{}'", text));
+    match (number(low)?, number(high)?) {
+        (low, high) if 0 <= low && low <= high => Ok((low, high)),
+        _ => Err(format!("invalid range '{}'", text)),
+    }
+}
+
+fn parse_value<T: FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("invalid number '{}'", text))
+}
+
+impl FromStr for Sizes {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Sizes, String> {
+        let fields: Vec<&str> = text.split(':').collect();
+        match fields[..] {
+            ["uniform", range] => {
+                let (min, max) = parse_range(range)?;
+                Ok(Sizes::Uniform { min, max })
+            }
+            ["power-law", range, exponent] => {
+                let (min, max) = parse_range(range)?;
+                Ok(Sizes::PowerLaw { min: min.max(1), max: max.max(1), exponent: parse_value(exponent)? })
+            }
+            ["bimodal", small, large, percent] => Ok(Sizes::Bimodal {
+                small: parse_range(small)?,
+                large: parse_range(large)?,
+                large_percent: parse_value::<u64>(percent)?.min(100),
+            }),
+            ["classes", ref classes @ ..] if !classes.is_empty() => {
+                Ok(Sizes::Classes(classes.iter().map(|class| parse_value(class)).collect::<Result<_, _>>()?))
+            }
+            _ => Err(format!("invalid size distribution '{}'", text)),
+        }
+    }
+}
+
+impl fmt::Display for Sizes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Sizes::Uniform { min, max } => write!(f, "uniform:{}-{}", min, max),
+            Sizes::PowerLaw { min, max, exponent } => write!(f, "power-law:{}-{}:{}", min, max, exponent),
+            Sizes::Bimodal { small, large, large_percent } => {
+                write!(f, "bimodal:{}-{}:{}-{}:{}", small.0, small.1, large.0, large.1, large_percent)
+            }
+            Sizes::Classes(classes) => {
+                f.write_str("classes")?;
+                classes.iter().try_for_each(|class| write!(f, ":{}", class))
+            }
+        }
+    }
+}
+
+impl Sizes {
+    // sample picks a request size.
+    pub fn sample(&self, source: &mut impl Source) -> i32 {
+        match self {
+            Sizes::Uniform { min, max } => source.range(*min, *max),
+            Sizes::PowerLaw { min, max, exponent } => {
+                // Invert the cumulative distribution of a bounded power law.
+                let (min, max, u) = (*min as f64, *max as f64, source.fraction());
+                let size = if (exponent - 1.0).abs() < 1e-9 {
+                    min * (max / min).powf(u)
+                } else {
+                    let e = 1.0 - exponent;
+                    ((max.powf(e) - min.powf(e)) * u + min.powf(e)).powf(1.0 / e)
+                };
+                (size as i32).clamp(min as i32, max as i32)
+            }
+            Sizes::Bimodal { small, large, large_percent } => {
+                let (low, high) = if source.below(100) < *large_percent { large } else { small };
+                source.range(*low, *high)
+            }
+            Sizes::Classes(classes) => classes[source.below(classes.len() as u64) as usize],
+        }
+    }
+}
+
+impl FromStr for Lifetime {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Lifetime, String> {
+        match text.split(':').collect::<Vec<_>>()[..] {
+            ["lifo"] => Ok(Lifetime::Lifo),
+            ["fifo"] => Ok(Lifetime::Fifo),
+            ["random"] => Ok(Lifetime::Random),
+            ["mixed"] => Ok(Lifetime::Mixed { long_lived_percent: 10 }),
+            ["mixed", percent] => Ok(Lifetime::Mixed { long_lived_percent: parse_value::<u64>(percent)?.min(100) }),
+            _ => Err(format!("invalid lifetime distribution '{}'", text)),
+        }
+    }
+}
+
+impl fmt::Display for Lifetime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lifetime::Lifo => f.write_str("lifo"),
+            Lifetime::Fifo => f.write_str("fifo"),
+            Lifetime::Random => f.write_str("random"),
+            Lifetime::Mixed { long_lived_percent } => write!(f, "mixed:{}", long_lived_percent),
+        }
+    }
+}
+
+impl FromStr for Growth {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Growth, String> {
+        match text.split(':').collect::<Vec<_>>()[..] {
+            ["none"] => Ok(Growth::None),
+            ["linear", step] => Ok(Growth::Linear { step: parse_value(step)? }),
+            ["geometric", factor] => Ok(Growth::Geometric { factor: parse_value(factor)? }),
+            _ => Err(format!("invalid growth pattern '{}'", text)),
+        }
+    }
+}
+
+impl fmt::Display for Growth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Growth::None => f.write_str("none"),
+            Growth::Linear { step } => write!(f, "linear:{}", step),
+            Growth::Geometric { factor } => write!(f, "geometric:{}", factor),
+        }
+    }
+}
+
+impl Growth {
+    // grow returns the new size of a block of the given size.
+    fn grow(&self, size: i32) -> i32 {
+        let size = match self {
+            Growth::None => size as f64,
+            Growth::Linear { step } => size as f64 + *step as f64,
+            Growth::Geometric { factor } => (size.max(1) as f64 * factor).ceil(),
+        };
+        size.clamp(0.0, i32::MAX as f64) as i32
+    }
+}
+
+// Workload is a complete synthetic workload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    pub sizes: Sizes,
+    pub lifetime: Lifetime,
+    pub growth: Growth,
+    // realloc_percent is how often a block is reallocated instead of freed,
+    // unless growth is none.
+    pub realloc_percent: u64,
+    // ops is the number of operations to generate.
+    pub ops: usize,
+    // max_live is the number of live blocks after which blocks are freed.
+    pub max_live: usize,
+}
+
+impl Default for Workload {
+    fn default() -> Workload {
+        Workload {
+            sizes: Sizes::PowerLaw { min: 8, max: 4096, exponent: 2.0 },
+            lifetime: Lifetime::Random,
+            growth: Growth::None,
+            realloc_percent: 20,
+            ops: 10000,
+            max_live: 100,
+        }
+    }
+}
+
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "sizes={} lifetime={} growth={} ops={} max-live={}", self.sizes, self.lifetime, self.growth, self.ops, self.max_live)?;
+        if self.growth != Growth::None {
+            write!(f, " realloc-percent={}", self.realloc_percent)?;
+        }
+        Ok(())
+    }
+}
+
+// Live is a live block while generating a workload.
+struct Live {
+    id: usize,
+    size: i32,
+    long_lived: bool,
+}
+
+impl Workload {
+    // generate produces the operations of the workload. The same seed always
+    // produces the same operations.
+    pub fn generate(&self, seed: u64) -> Vec<Op> {
+        let mut rng = Rng::new(seed);
+        let mut live: Vec<Live> = Vec::new();
+        let mut ops = Vec::with_capacity(self.ops);
+        let mut next_id = 0;
+        while ops.len() < self.ops {
+            // Allocate half of the time until max_live blocks are live.
+            if live.is_empty() || (live.len() < self.max_live && rng.below(2) == 0) {
+                let id = next_id;
+                next_id += 1;
+                let size = self.sizes.sample(&mut rng);
+                let long_lived = match self.lifetime {
+                    Lifetime::Mixed { long_lived_percent } => rng.below(100) < long_lived_percent,
+                    _ => false,
+                };
+                ops.push(Op::Alloc { id, size });
+                live.push(Live { id, size, long_lived });
+                continue;
+            }
+            let i = self.victim(&live, &mut rng);
+            if self.growth != Growth::None && rng.below(100) < self.realloc_percent {
+                let block = &mut live[i];
+                block.size = self.growth.grow(block.size);
+                ops.push(Op::Realloc { id: block.id, size: block.size });
+            } else {
+                ops.push(Op::Dealloc { id: live.remove(i).id });
+            }
+        }
+        ops
+    }
+
+    // victim picks the live block to free or reallocate next.
+    fn victim(&self, live: &[Live], rng: &mut Rng) -> usize {
+        match self.lifetime {
+            Lifetime::Lifo => live.len() - 1,
+            Lifetime::Fifo => 0,
+            Lifetime::Random => rng.below(live.len() as u64) as usize,
+            Lifetime::Mixed { .. } => {
+                let short: Vec<usize> = (0..live.len()).filter(|&i| !live[i].long_lived).collect();
+                if short.is_empty() {
+                    rng.below(live.len() as u64) as usize
+                } else {
+                    short[rng.below(short.len() as u64) as usize]
+                }
+            }
+        }
+    }
+}
+
+// PRESETS lists the named workloads, with a description.
+pub const PRESETS: &[(&str, &str)] = &[
+    ("small", "small power-law requests freed in random order"),
+    ("stack", "uniform requests freed in LIFO order"),
+    ("queue", "uniform requests freed in FIFO order"),
+    ("bimodal", "mostly small requests with a few large ones"),
+    ("classes", "requests of a few fixed size classes"),
+    ("mixed", "short-lived requests among long-lived ones"),
+    ("growing", "buffers that keep doubling through realloc"),
+];
+
+// preset returns the named workload.
+pub fn preset(name: &str) -> Option<Workload> {
+    let default = Workload::default();
+    let workload = match name {
+        "small" => default,
+        "stack" => Workload { sizes: Sizes::Uniform { min: 1, max: 1024 }, lifetime: Lifetime::Lifo, ..default },
+        "queue" => Workload { sizes: Sizes::Uniform { min: 1, max: 1024 }, lifetime: Lifetime::Fifo, ..default },
+        "bimodal" => Workload {
+            sizes: Sizes::Bimodal { small: (8, 64), large: (4096, 32768), large_percent: 5 },
+            ..default
+        },
+        "classes" => Workload { sizes: Sizes::Classes(vec![16, 32, 64, 128, 256, 512]), ..default },
+        "mixed" => Workload { lifetime: Lifetime::Mixed { long_lived_percent: 10 }, ..default },
+        "growing" => Workload {
+            sizes: Sizes::Uniform { min: 8, max: 64 },
+            growth: Growth::Geometric { factor: 2.0 },
+            realloc_percent: 60,
+            max_live: 10,
+            ..default
+        },
+        _ => return None,
+    };
+    Some(workload)
+}
+
+// load returns the operations of a workload given by preset name or as the
+// path of a trace file. Presets are generated with the seed.
+pub fn load(workload: &str, seed: u64) -> Result<Vec<Op>, Box<dyn Error>> {
+    match preset(workload) {
+        Some(preset) => Ok(preset.generate(seed)),
+        None => trace::load(workload),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn generation_is_deterministic_per_seed() {
+        for &(name, _) in PRESETS {
+            let workload = preset(name).unwrap();
+            assert_eq!(workload.generate(7), workload.generate(7), "{}", name);
+            assert_ne!(workload.generate(7), workload.generate(8), "{}", name);
+        }
+    }
+
+    #[test]
+    fn operations_target_live_blocks() {
+        for &(name, _) in PRESETS {
+            let workload = Workload { ops: 2000, ..preset(name).unwrap() };
+            let ops = workload.generate(1);
+            assert_eq!(ops.len(), workload.ops, "{}", name);
+            let mut live = HashSet::new();
+            for op in ops {
+                match op {
+                    Op::Alloc { id, .. } => assert!(live.insert(id), "{}: #{} allocated twice", name, id),
+                    Op::Dealloc { id } => assert!(live.remove(&id), "{}: #{} isn't live", name, id),
+                    Op::Realloc { id, .. } => assert!(live.contains(&id), "{}: #{} isn't live", name, id),
+                    Op::Store { .. } | Op::Load { .. } => panic!("{}: unexpected {}", name, op),
+                }
+                assert!(live.len() <= workload.max_live, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn sizes_stay_in_range() {
+        let mut rng = Rng::new(1);
+        for text in ["uniform:1-1024", "power-law:16-65536:2", "bimodal:16-64:4096-16384:10", "classes:16:32:64"] {
+            let sizes: Sizes = text.parse().unwrap();
+            assert_eq!(sizes.to_string(), text);
+            for _ in 0..1000 {
+                let size = sizes.sample(&mut rng);
+                let valid = match &sizes {
+                    Sizes::Uniform { min, max } | Sizes::PowerLaw { min, max, .. } => (*min..=*max).contains(&size),
+                    Sizes::Bimodal { small, large, .. } => (small.0..=small.1).contains(&size) || (large.0..=large.1).contains(&size),
+                    Sizes::Classes(classes) => classes.contains(&size),
+                };
+                assert!(valid, "{}: {}", text, size);
+            }
+        }
+    }
+
+    #[test]
+    fn growth() {
+        assert_eq!(Growth::Linear { step: 64 }.grow(10), 74);
+        assert_eq!(Growth::Geometric { factor: 2.0 }.grow(0), 2);
+        assert_eq!(Growth::Geometric { factor: 2.0 }.grow(i32::MAX / 2 + 1), i32::MAX);
+    }
+}